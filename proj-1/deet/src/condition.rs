@@ -0,0 +1,93 @@
+use crate::dwarf_data::{DwarfData, Encoding, Type, TypeKind};
+use crate::expression::{self, Context, Evaluator, Expr};
+use std::fmt;
+
+/// A number read out of the inferior (or typed by the user), for comparisons.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scalar {
+    Int(i64),
+    Float(f64),
+}
+
+impl Scalar {
+    /// Interprets the raw bytes of a variable according to its type.
+    pub fn from_bytes(dtype: &Type, bytes: &[u8]) -> Scalar {
+        let mut buf = [0u8; 8];
+        let len = bytes.len().min(8);
+        buf[..len].copy_from_slice(&bytes[..len]);
+        let raw = u64::from_le_bytes(buf);
+        if dtype.is_float() && len == 4 {
+            Scalar::Float(f32::from_bits(raw as u32) as f64)
+        } else if dtype.is_float() && len == 8 {
+            Scalar::Float(f64::from_bits(raw))
+        } else if dtype.is_signed() {
+            let shift = 64 - 8 * len.max(1) as u32;
+            Scalar::Int(((raw << shift) as i64) >> shift)
+        } else {
+            Scalar::Int(raw as i64)
+        }
+    }
+
+    /// Encodes the value as a variable of the given type, converting between integers and
+    /// floating point as C assignment would.
+    pub fn to_bytes(&self, dtype: &Type) -> Vec<u8> {
+        let bytes = match (&dtype.kind, dtype.size) {
+            (TypeKind::Base(Encoding::Float), 4) => {
+                (self.as_f64() as f32).to_bits().to_le_bytes().to_vec()
+            }
+            (TypeKind::Base(Encoding::Float), 8) => self.as_f64().to_bits().to_le_bytes().to_vec(),
+            (TypeKind::Base(Encoding::Boolean), _) => {
+                (self.is_true() as u64).to_le_bytes().to_vec()
+            }
+            _ => match *self {
+                Scalar::Int(value) => value.to_le_bytes().to_vec(),
+                Scalar::Float(value) => (value as i64).to_le_bytes().to_vec(),
+            },
+        };
+        let mut bytes = bytes;
+        bytes.resize(dtype.size, 0);
+        bytes
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Scalar::Int(value) => value as f64,
+            Scalar::Float(value) => value,
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        self.as_f64() != 0.0
+    }
+}
+
+/// A breakpoint condition: any C expression, which holds if it evaluates to something nonzero,
+/// e.g. `i == 3`, `$rdi > 0x10` or `p != 0 && p->next == 0`.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str, data: &DwarfData) -> Result<Condition, String> {
+        let text = text.trim();
+        Ok(Condition {
+            text: text.to_string(),
+            expr: expression::parse(text, data)?,
+        })
+    }
+
+    /// Evaluates the condition, reading variables and registers through `context`.
+    pub fn evaluate(&self, data: &DwarfData, context: &dyn Context) -> Result<bool, String> {
+        let evaluator = Evaluator::new(data, context);
+        let value = evaluator.evaluate(&self.expr)?;
+        Ok(evaluator.scalar(&value)?.is_true())
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,781 @@
+//! Core files, for post-mortem debugging: the registers and memory of a process at the moment it
+//! died, as the kernel dumped them in an ELF file of type ET_CORE.
+//!
+//! The `PT_LOAD` segments hold the process's memory, except that the kernel usually leaves out
+//! pages that can be read back from a file (such as the program's code); the `NT_FILE` note says
+//! which files were mapped where, so those are read from disk instead. The other notes hold the
+//! registers of each thread (`NT_PRSTATUS`), the command line (`NT_PRPSINFO`) and the auxiliary
+//! vector (`NT_AUXV`).
+//!
+//! "gcore" writes the same kind of file from a live inferior.
+
+use crate::inferior::{self, Inferior};
+use crate::process::{Process, AT_ENTRY};
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+const ET_EXEC: u16 = 2;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRFPREG: u32 = 2;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const PAGE_SIZE: usize = 4096;
+/// Memory is copied from the inferior into a core file this much at a time
+const CHUNK_SIZE: usize = 1 << 20;
+
+// Sizes of and offsets into struct elf_prstatus and struct elf_prpsinfo on x86-64
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_PPID: usize = 36;
+const PRSTATUS_REGS: usize = 112;
+const PRSTATUS_FPVALID: usize = 328;
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_FNAME: usize = 40;
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+/// Memory the kernel dumped into the core: `len` bytes at `addr`, stored at `offset` in the file.
+struct Segment {
+    addr: usize,
+    offset: usize,
+    len: usize,
+}
+
+/// A file the process had mapped into memory: bytes `start..end` came from `offset` in `path`.
+struct MappedFile {
+    start: usize,
+    end: usize,
+    offset: usize,
+    path: String,
+}
+
+pub struct CoreFile {
+    mmap: memmap::Mmap,
+    segments: Vec<Segment>,
+    mapped_files: Vec<MappedFile>,
+    /// Registers of the thread that died (the first NT_PRSTATUS)
+    regs: libc::user_regs_struct,
+    fpregs: Option<libc::user_fpregs_struct>,
+    pub pid: i32,
+    /// The signal that killed the process, if it was killed by one
+    pub signal: Option<Signal>,
+    /// The command line, as far as the kernel kept it
+    pub command: String,
+    pub num_threads: usize,
+    auxv: Vec<(u64, u64)>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// Reads a C struct out of a note's contents, or returns None if the note is too short.
+fn read_struct<T>(bytes: &[u8], offset: usize) -> Option<T> {
+    let bytes = bytes.get(offset..offset + std::mem::size_of::<T>())?;
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Reads a fixed-size, NUL-padded string field.
+fn read_c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Splits the contents of a PT_NOTE segment into (type, name, description) triples.
+fn parse_notes(bytes: &[u8]) -> Vec<(u32, &[u8], &[u8])> {
+    let align = |len: usize| (len + 3) & !3;
+    let mut notes = Vec::new();
+    let mut offset = 0;
+    while let (Some(namesz), Some(descsz), Some(note_type)) = (
+        read_u32(bytes, offset),
+        read_u32(bytes, offset + 4),
+        read_u32(bytes, offset + 8),
+    ) {
+        let name_start = offset + 12;
+        let desc_start = name_start + align(namesz as usize);
+        let desc_end = desc_start + descsz as usize;
+        match (
+            bytes.get(name_start..name_start + namesz as usize),
+            bytes.get(desc_start..desc_end),
+        ) {
+            (Some(name), Some(desc)) => notes.push((note_type, name, desc)),
+            _ => break,
+        }
+        offset = desc_start + align(descsz as usize);
+    }
+    notes
+}
+
+impl CoreFile {
+    /// Loads an x86-64 ELF core file. Returns a description of the problem if it isn't one.
+    pub fn open(path: &str) -> Result<CoreFile, String> {
+        let file = fs::File::open(path).map_err(|err| err.to_string())?;
+        let mmap = unsafe { memmap::Mmap::map(&file).map_err(|err| err.to_string())? };
+        let not_core = || "not an x86-64 core file".to_string();
+        if !mmap.starts_with(b"\x7fELF\x02\x01")
+            || read_u16(&mmap, 16) != Some(ET_CORE)
+            || read_u16(&mmap, 18) != Some(EM_X86_64)
+        {
+            return Err(not_core());
+        }
+        let phoff = read_u64(&mmap, 32).ok_or_else(not_core)? as usize;
+        let phentsize = read_u16(&mmap, 54).ok_or_else(not_core)? as usize;
+        let phnum = read_u16(&mmap, 56).ok_or_else(not_core)? as usize;
+
+        let mut segments = Vec::new();
+        let mut notes = Vec::new();
+        for index in 0..phnum {
+            let header = index
+                .checked_mul(phentsize)
+                .and_then(|header| header.checked_add(phoff))
+                .filter(|header| *header < mmap.len())
+                .ok_or_else(not_core)?;
+            let p_type = read_u32(&mmap, header).ok_or_else(not_core)?;
+            let offset = read_u64(&mmap, header + 8).ok_or_else(not_core)? as usize;
+            let addr = read_u64(&mmap, header + 16).ok_or_else(not_core)? as usize;
+            let filesz = read_u64(&mmap, header + 32).ok_or_else(not_core)? as usize;
+            // A truncated core (e.g. from hitting the core size limit) only has some of its memory
+            let len = filesz.min(mmap.len().saturating_sub(offset));
+            match p_type {
+                // Memory can't run past the top of the address space
+                PT_LOAD if len > 0 && addr.checked_add(len).is_some() => {
+                    segments.push(Segment { addr, offset, len })
+                }
+                PT_NOTE => {
+                    let bytes = mmap.get(offset..offset + len).ok_or_else(not_core)?;
+                    notes.extend(parse_notes(bytes))
+                }
+                _ => {}
+            }
+        }
+
+        let mut prstatus = Vec::new();
+        let mut fpregs = None;
+        let mut psinfo = None;
+        let mut auxv = Vec::new();
+        let mut mapped_files = Vec::new();
+        for (note_type, name, desc) in notes {
+            if name != b"CORE\0" {
+                continue;
+            }
+            match note_type {
+                NT_PRSTATUS => prstatus.push(desc),
+                // Every thread has one; keep the one belonging to the thread that died
+                NT_PRFPREG if fpregs.is_none() => fpregs = read_struct(desc, 0),
+                NT_PRPSINFO => psinfo = Some(desc),
+                NT_AUXV => {
+                    auxv = desc
+                        .chunks_exact(16)
+                        .filter_map(|pair| Some((read_u64(pair, 0)?, read_u64(pair, 8)?)))
+                        .collect()
+                }
+                NT_FILE => mapped_files = Self::parse_file_note(desc).ok_or_else(not_core)?,
+                _ => {}
+            }
+        }
+
+        // The kernel writes the thread that received the signal first
+        let status = prstatus.first().ok_or("no registers found in core file")?;
+        let regs = read_struct(status, PRSTATUS_REGS).ok_or_else(not_core)?;
+        let signal = read_u16(status, PRSTATUS_CURSIG).and_then(|sig| Signal::try_from(sig as i32).ok());
+        let pid = read_u32(status, PRSTATUS_PID).unwrap_or(0) as i32;
+        let command = match psinfo {
+            Some(desc) => {
+                let args = read_c_string(desc.get(PRPSINFO_PSARGS..).unwrap_or(&[]));
+                if args.is_empty() {
+                    read_c_string(desc.get(PRPSINFO_FNAME..PRPSINFO_PSARGS).unwrap_or(&[]))
+                } else {
+                    args.trim_end().to_string()
+                }
+            }
+            None => String::new(),
+        };
+
+        Ok(CoreFile {
+            num_threads: prstatus.len(),
+            mmap,
+            segments,
+            mapped_files,
+            regs,
+            fpregs,
+            pid,
+            signal,
+            command,
+            auxv,
+        })
+    }
+
+    /// Parses NT_FILE: a count and page size, then (start, end, offset in pages) for each mapping,
+    /// then the mappings' paths as NUL-terminated strings.
+    fn parse_file_note(desc: &[u8]) -> Option<Vec<MappedFile>> {
+        let count = read_u64(desc, 0)? as usize;
+        let page_size = read_u64(desc, 8)? as usize;
+        let paths_start = count.checked_mul(24)?.checked_add(16)?;
+        let mut paths = desc.get(paths_start..)?.split(|byte| *byte == 0);
+        let mut files = Vec::with_capacity(count);
+        for index in 0..count {
+            let entry = 16 + index * 24;
+            files.push(MappedFile {
+                start: read_u64(desc, entry)? as usize,
+                end: read_u64(desc, entry + 8)? as usize,
+                offset: (read_u64(desc, entry + 16)? as usize).checked_mul(page_size)?,
+                path: String::from_utf8_lossy(paths.next()?).into_owned(),
+            });
+        }
+        Some(files)
+    }
+
+    /// Returns false if `executable` clearly isn't the program that dumped this core, i.e. it is
+    /// a non-PIE executable whose entry point differs from the one the process had.
+    pub fn matches_executable(&self, executable: &str) -> bool {
+        let mut header = [0u8; 32];
+        let read = fs::File::open(executable).and_then(|mut file| file.read_exact(&mut header));
+        if read.is_err() || read_u16(&header, 16) != Some(ET_EXEC) {
+            return true;
+        }
+        match (read_u64(&header, 24), self.auxv_entry(AT_ENTRY)) {
+            (Some(entry), Some(core_entry)) => entry == core_entry,
+            _ => true,
+        }
+    }
+
+    /// Reads as much as possible of `len` bytes at `addr` from a single segment or mapped file.
+    fn read_chunk(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        // What the core holds is what memory looked like; mapped files may have been written to
+        if let Some(segment) = self
+            .segments
+            .iter()
+            .find(|segment| segment.addr <= addr && addr < segment.addr + segment.len)
+        {
+            let start = segment.offset + (addr - segment.addr);
+            let len = len.min(segment.addr + segment.len - addr);
+            return Some(self.mmap[start..start + len].to_vec());
+        }
+        let file = self
+            .mapped_files
+            .iter()
+            .find(|file| file.start <= addr && addr < file.end)?;
+        let mut bytes = vec![0u8; len.min(file.end - addr)];
+        let mut reader = fs::File::open(&file.path).ok()?;
+        reader
+            .seek(SeekFrom::Start(file.offset.checked_add(addr - file.start)? as u64))
+            .ok()?;
+        reader.read_exact(&mut bytes).ok()?;
+        Some(bytes)
+    }
+}
+
+impl Process for CoreFile {
+    fn regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.regs)
+    }
+
+    fn fpregs(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        self.fpregs.ok_or(nix::Error::Sys(Errno::ENODATA))
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let chunk = self
+                .read_chunk(addr + bytes.len(), len - bytes.len())
+                .ok_or(nix::Error::Sys(Errno::EFAULT))?;
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+
+    fn auxv_entry(&self, key: u64) -> Option<u64> {
+        self.auxv.iter().find(|(k, _)| *k == key).map(|(_, value)| *value)
+    }
+}
+
+/// One line of /proc/<pid>/maps.
+struct Mapping {
+    start: usize,
+    end: usize,
+    /// PF_R, PF_W and PF_X, as in a program header
+    flags: u32,
+    offset: usize,
+    path: String,
+}
+
+fn read_maps(pid: Pid) -> io::Result<Vec<Mapping>> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    let mut mappings = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode [path]
+        let fields: Vec<&str> = line.splitn(6, ' ').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let range: Vec<usize> = fields[0]
+            .split('-')
+            .filter_map(|addr| usize::from_str_radix(addr, 16).ok())
+            .collect();
+        let perms = fields[1].as_bytes();
+        let offset = usize::from_str_radix(fields[2], 16).unwrap_or(0);
+        if range.len() != 2 || perms.len() < 3 {
+            continue;
+        }
+        let flag = |index: usize, letter: u8, flag: u32| if perms[index] == letter { flag } else { 0 };
+        mappings.push(Mapping {
+            start: range[0],
+            end: range[1],
+            flags: flag(0, b'r', PF_R) | flag(1, b'w', PF_W) | flag(2, b'x', PF_X),
+            offset,
+            path: fields.get(5).map(|path| path.trim_start().to_string()).unwrap_or_default(),
+        });
+    }
+    Ok(mappings)
+}
+
+fn nix_to_io(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err.as_errno().unwrap_or(Errno::EIO) as i32)
+}
+
+/// Returns the bytes of a C struct, for copying into a note.
+fn struct_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
+/// Appends a note named "CORE" to the contents of a PT_NOTE segment.
+fn push_note(notes: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    notes.extend_from_slice(&5u32.to_le_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&note_type.to_le_bytes());
+    notes.extend_from_slice(b"CORE\0\0\0\0");
+    notes.extend_from_slice(desc);
+    notes.resize((notes.len() + 3) & !3, 0);
+}
+
+/// Copies a string into a fixed-size field, leaving room for the NUL.
+fn put_c_string(field: &mut [u8], string: &str) {
+    let len = string.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&string.as_bytes()[..len]);
+}
+
+/// Builds the notes of a core file of `inferior`: the process's command line, the registers of
+/// every thread (the current one first, which is the one a debugger will show), the auxiliary
+/// vector and the mapped files.
+fn build_notes(inferior: &Inferior, mappings: &[Mapping]) -> io::Result<Vec<u8>> {
+    let pid = inferior.pid();
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+    let ppid = status
+        .lines()
+        .find(|line| line.starts_with("PPid:"))
+        .and_then(|line| line["PPid:".len()..].trim().parse::<i32>().ok())
+        .unwrap_or(0);
+    let mut notes = Vec::new();
+
+    let mut psinfo = vec![0u8; PRPSINFO_SIZE];
+    psinfo[PRPSINFO_PID..PRPSINFO_PID + 4].copy_from_slice(&pid.as_raw().to_le_bytes());
+    let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    put_c_string(&mut psinfo[PRPSINFO_FNAME..PRPSINFO_PSARGS], comm.trim_end());
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+    let args = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+    put_c_string(&mut psinfo[PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN], args.trim_end());
+    push_note(&mut notes, NT_PRPSINFO, &psinfo);
+
+    let current = inferior.current_thread();
+    let others = inferior.threads().iter().filter(|thread| thread.tid != current.tid);
+    for thread in std::iter::once(current).chain(others) {
+        let regs = ptrace::getregs(thread.tid).map_err(nix_to_io)?;
+        let fpregs = inferior::get_fpregs(thread.tid).ok();
+        let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+        prstatus[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&thread.tid.as_raw().to_le_bytes());
+        prstatus[PRSTATUS_PPID..PRSTATUS_PPID + 4].copy_from_slice(&ppid.to_le_bytes());
+        prstatus[PRSTATUS_REGS..PRSTATUS_FPVALID].copy_from_slice(struct_bytes(&regs));
+        prstatus[PRSTATUS_FPVALID] = fpregs.is_some() as u8;
+        push_note(&mut notes, NT_PRSTATUS, &prstatus);
+        if let Some(fpregs) = fpregs {
+            push_note(&mut notes, NT_PRFPREG, struct_bytes(&fpregs));
+        }
+    }
+
+    if let Ok(auxv) = fs::read(format!("/proc/{}/auxv", pid)) {
+        push_note(&mut notes, NT_AUXV, &auxv);
+    }
+
+    let files: Vec<&Mapping> = mappings.iter().filter(|mapping| mapping.path.starts_with('/')).collect();
+    let mut file_note = Vec::new();
+    file_note.extend_from_slice(&(files.len() as u64).to_le_bytes());
+    file_note.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+    for file in &files {
+        for value in &[file.start, file.end, file.offset / PAGE_SIZE] {
+            file_note.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+    }
+    for file in &files {
+        file_note.extend_from_slice(file.path.as_bytes());
+        file_note.push(0);
+    }
+    push_note(&mut notes, NT_FILE, &file_note);
+    Ok(notes)
+}
+
+/// Reads `len` bytes of the inferior's memory, without breakpoints. Pages that can't be read (e.g.
+/// the part of a file mapping past the end of the file) are left as zeros.
+fn read_memory(inferior: &Inferior, addr: usize, len: usize) -> Vec<u8> {
+    inferior.read_code(addr, len).unwrap_or_else(|_| {
+        (0..len)
+            .step_by(PAGE_SIZE)
+            .flat_map(|offset| {
+                let len = PAGE_SIZE.min(len - offset);
+                inferior
+                    .read_code(addr + offset, len)
+                    .unwrap_or_else(|_| vec![0; len])
+            })
+            .collect()
+    })
+}
+
+/// Writes a core file of the stopped inferior to `path`, holding all of its readable memory and
+/// the registers of all of its threads, in the same form the kernel uses.
+pub fn write(inferior: &Inferior, path: &str) -> io::Result<()> {
+    let mappings: Vec<Mapping> = read_maps(inferior.pid())?
+        .into_iter()
+        .filter(|mapping| mapping.flags & PF_R != 0)
+        .collect();
+    let notes = build_notes(inferior, &mappings)?;
+
+    let phnum = mappings.len() + 1;
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let memory_offset = (notes_offset + notes.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+    let mut header = Vec::with_capacity(memory_offset);
+    header.extend_from_slice(b"\x7fELF\x02\x01\x01");
+    header.resize(16, 0);
+    header.extend_from_slice(&ET_CORE.to_le_bytes());
+    header.extend_from_slice(&EM_X86_64.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    header.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    header.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    header.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(phnum as u16).to_le_bytes());
+    header.extend_from_slice(&[0u8; 6]); // no section headers
+
+    let mut push_program_header = |p_type: u32, flags: u32, offset: usize, addr: usize, len: usize, align: usize| {
+        header.extend_from_slice(&p_type.to_le_bytes());
+        header.extend_from_slice(&flags.to_le_bytes());
+        for value in &[offset, addr, 0, len, len, align] {
+            header.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+    };
+    push_program_header(PT_NOTE, 0, notes_offset, 0, notes.len(), 4);
+    let mut offset = memory_offset;
+    for mapping in &mappings {
+        let len = mapping.end - mapping.start;
+        push_program_header(PT_LOAD, mapping.flags, offset, mapping.start, len, PAGE_SIZE);
+        offset += len;
+    }
+    header.extend_from_slice(&notes);
+    header.resize(memory_offset, 0);
+
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    file.write_all(&header)?;
+    for mapping in &mappings {
+        let mut addr = mapping.start;
+        while addr < mapping.end {
+            let len = CHUNK_SIZE.min(mapping.end - addr);
+            file.write_all(&read_memory(inferior, addr, len))?;
+            addr += len;
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples;
+    use std::path::PathBuf;
+
+    /// Lays out a core file the way `write` does: the ELF header, the program headers, the notes
+    /// and then each segment's memory.
+    fn core_bytes(notes: &[u8], memory: &[(usize, &[u8])]) -> Vec<u8> {
+        let phnum = memory.len() + 1;
+        let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+        let mut bytes = b"\x7fELF\x02\x01\x01".to_vec();
+        bytes.resize(16, 0);
+        bytes.extend_from_slice(&ET_CORE.to_le_bytes());
+        bytes.extend_from_slice(&EM_X86_64.to_le_bytes());
+        bytes.resize(32, 0);
+        bytes.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+        bytes.resize(54, 0);
+        bytes.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&(phnum as u16).to_le_bytes());
+        bytes.resize(ELF_HEADER_SIZE, 0);
+        let mut push_program_header = |p_type: u32, offset: usize, addr: usize, len: usize| {
+            bytes.extend_from_slice(&p_type.to_le_bytes());
+            bytes.extend_from_slice(&PF_R.to_le_bytes());
+            for value in &[offset, addr, 0, len, len, 0] {
+                bytes.extend_from_slice(&(*value as u64).to_le_bytes());
+            }
+        };
+        push_program_header(PT_NOTE, notes_offset, 0, notes.len());
+        let mut offset = notes_offset + notes.len();
+        for (addr, contents) in memory {
+            push_program_header(PT_LOAD, offset, *addr, contents.len());
+            offset += contents.len();
+        }
+        bytes.extend_from_slice(notes);
+        for (_, contents) in memory {
+            bytes.extend_from_slice(contents);
+        }
+        bytes
+    }
+
+    /// Writes `bytes` to a file of its own in the temporary directory.
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("deet-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn open(name: &str, bytes: &[u8]) -> Result<CoreFile, String> {
+        let path = temp_file(name, bytes);
+        let core = CoreFile::open(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        core
+    }
+
+    fn file_note(page_size: u64, files: &[(u64, u64, u64, &str)]) -> Vec<u8> {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&(files.len() as u64).to_le_bytes());
+        desc.extend_from_slice(&page_size.to_le_bytes());
+        for (start, end, offset, _) in files {
+            for value in &[start, end, offset] {
+                desc.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        for (_, _, _, path) in files {
+            desc.extend_from_slice(path.as_bytes());
+            desc.push(0);
+        }
+        desc
+    }
+
+    #[test]
+    fn notes_are_split_and_padded() {
+        let mut bytes = Vec::new();
+        // A name and description that both need padding to four bytes
+        for value in &[6u32, 3, 7] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"LINUX\0\0\0abc\0");
+        for value in &[5u32, 0, NT_AUXV] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"CORE\0\0\0\0");
+        let notes = parse_notes(&bytes);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0], (7, &b"LINUX\0"[..], &b"abc"[..]));
+        assert_eq!(notes[1], (NT_AUXV, &b"CORE\0"[..], &b""[..]));
+    }
+
+    #[test]
+    fn truncated_notes_are_dropped() {
+        let mut bytes = Vec::new();
+        for value in &[5u32, 100, NT_PRSTATUS] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"CORE\0\0\0\0short");
+        assert!(parse_notes(&bytes).is_empty());
+        assert!(parse_notes(&bytes[..7]).is_empty());
+        for value in &[u32::MAX, u32::MAX, NT_PRSTATUS] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        assert!(parse_notes(&bytes).is_empty());
+    }
+
+    #[test]
+    fn mapped_files() {
+        let desc = file_note(
+            4096,
+            &[(0x400000, 0x401000, 0, "/bin/true"), (0x601000, 0x602000, 2, "/lib/libc.so")],
+        );
+        let files = CoreFile::parse_file_note(&desc).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].start, files[0].end, files[0].offset), (0x400000, 0x401000, 0));
+        assert_eq!(files[0].path, "/bin/true");
+        assert_eq!((files[1].start, files[1].end, files[1].offset), (0x601000, 0x602000, 0x2000));
+        assert_eq!(files[1].path, "/lib/libc.so");
+    }
+
+    #[test]
+    fn bad_file_notes() {
+        // Fewer paths than mappings
+        let mut desc = file_note(4096, &[(0x400000, 0x401000, 0, "/bin/true")]);
+        desc[0] = 2;
+        assert!(CoreFile::parse_file_note(&desc).is_none());
+        // A count so large that the size of the table overflows
+        desc[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(CoreFile::parse_file_note(&desc).is_none());
+        assert!(CoreFile::parse_file_note(&desc[..12]).is_none());
+        // A file offset that overflows once scaled by the page size
+        let desc = file_note(4096, &[(0x400000, 0x401000, u64::MAX / 2, "/bin/true")]);
+        assert!(CoreFile::parse_file_note(&desc).is_none());
+    }
+
+    #[test]
+    fn open_core() {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401136;
+        regs.rsp = 0x7ffd_0000_1000;
+        let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+        prstatus[PRSTATUS_CURSIG..PRSTATUS_CURSIG + 2].copy_from_slice(&11u16.to_le_bytes());
+        prstatus[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&1234i32.to_le_bytes());
+        prstatus[PRSTATUS_REGS..PRSTATUS_FPVALID].copy_from_slice(struct_bytes(&regs));
+        let mut psinfo = vec![0u8; PRPSINFO_SIZE];
+        put_c_string(&mut psinfo[PRPSINFO_FNAME..PRPSINFO_PSARGS], "segfault");
+        put_c_string(&mut psinfo[PRPSINFO_PSARGS..], "./segfault arg ");
+        let mut auxv = Vec::new();
+        for value in &[AT_ENTRY, 0x401020, 0, 0] {
+            auxv.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut notes = Vec::new();
+        push_note(&mut notes, NT_PRPSINFO, &psinfo);
+        push_note(&mut notes, NT_PRSTATUS, &prstatus);
+        push_note(&mut notes, NT_PRSTATUS, &vec![0u8; PRSTATUS_SIZE]);
+        push_note(&mut notes, NT_AUXV, &auxv);
+        push_note(&mut notes, NT_FILE, &file_note(4096, &[]));
+
+        let core = open("open_core", &core_bytes(&notes, &[(0x10000, b"hello, world")])).unwrap();
+        assert_eq!(core.pid, 1234);
+        assert_eq!(core.signal, Some(Signal::SIGSEGV));
+        assert_eq!(core.command, "./segfault arg");
+        assert_eq!(core.num_threads, 2);
+        let loaded = core.regs().unwrap();
+        assert_eq!((loaded.rip, loaded.rsp), (regs.rip, regs.rsp));
+        assert!(core.fpregs().is_err());
+        assert_eq!(core.auxv_entry(AT_ENTRY), Some(0x401020));
+        assert_eq!(core.read_bytes(0x10007, 5).unwrap(), b"world");
+        assert!(core.read_bytes(0x10007, 6).is_err());
+    }
+
+    #[test]
+    fn gcore_reads_back() {
+        let program = samples::path("hello");
+        let mut inferior = Inferior::new(&program, &vec!["arg".to_string()]).unwrap();
+        let regs = ptrace::getregs(inferior.tid()).unwrap();
+        let code = inferior.read_bytes(regs.rip as usize, 16).unwrap();
+        let entry = inferior.auxv_entry(AT_ENTRY);
+        assert!(entry.is_some());
+        let path = std::env::temp_dir().join(format!("deet-{}-gcore", std::process::id()));
+        let written = write(&inferior, path.to_str().unwrap());
+        inferior.kill();
+        written.unwrap();
+        let core = CoreFile::open(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let core = core.unwrap();
+
+        assert_eq!(core.pid, inferior.pid().as_raw());
+        assert_eq!(core.num_threads, 1);
+        assert_eq!(core.signal, None);
+        let command = format!("{} arg", program);
+        assert_eq!(core.command, command[..command.len().min(PSARGS_LEN - 1)].trim_end());
+        let loaded = core.regs().unwrap();
+        assert_eq!((loaded.rip, loaded.rsp, loaded.fs_base), (regs.rip, regs.rsp, regs.fs_base));
+        assert!(core.fpregs().is_ok());
+        assert_eq!(core.read_bytes(regs.rip as usize, 16).unwrap(), code);
+        assert_eq!(core.auxv_entry(AT_ENTRY), entry);
+        assert!(core.matches_executable(&program));
+    }
+
+    #[test]
+    fn note_encoding() {
+        // The registers fill struct elf_prstatus from pr_reg up to pr_fpvalid
+        assert_eq!(PRSTATUS_REGS + std::mem::size_of::<libc::user_regs_struct>(), PRSTATUS_FPVALID);
+        assert_eq!(PRPSINFO_PSARGS + PSARGS_LEN, PRPSINFO_SIZE);
+
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401136;
+        regs.rax = 42;
+        regs.fs_base = 0x7f00_0000_0740;
+        let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        fpregs.mxcsr = 0x1f80;
+        let mut psinfo = vec![0u8; PRPSINFO_SIZE];
+        psinfo[PRPSINFO_PID..PRPSINFO_PID + 4].copy_from_slice(&99i32.to_le_bytes());
+        put_c_string(&mut psinfo[PRPSINFO_FNAME..PRPSINFO_PSARGS], "a_rather_long_program_name");
+        put_c_string(&mut psinfo[PRPSINFO_PSARGS..], "./count 3");
+        let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+        prstatus[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&100i32.to_le_bytes());
+        prstatus[PRSTATUS_PPID..PRSTATUS_PPID + 4].copy_from_slice(&1i32.to_le_bytes());
+        prstatus[PRSTATUS_REGS..PRSTATUS_FPVALID].copy_from_slice(struct_bytes(&regs));
+        prstatus[PRSTATUS_FPVALID] = 1;
+        let mut notes = Vec::new();
+        push_note(&mut notes, NT_PRPSINFO, &psinfo);
+        push_note(&mut notes, NT_PRSTATUS, &prstatus);
+        push_note(&mut notes, NT_PRFPREG, struct_bytes(&fpregs));
+        assert_eq!(notes.len() % 4, 0);
+
+        let parsed = parse_notes(&notes);
+        let types: Vec<u32> = parsed.iter().map(|(note_type, _, _)| *note_type).collect();
+        assert_eq!(types, vec![NT_PRPSINFO, NT_PRSTATUS, NT_PRFPREG]);
+        assert!(parsed.iter().all(|(_, name, _)| *name == b"CORE\0"));
+        let (psinfo, status, fp) = (parsed[0].2, parsed[1].2, parsed[2].2);
+        assert_eq!(read_u32(psinfo, PRPSINFO_PID), Some(99));
+        // pr_fname holds 16 bytes, including the NUL
+        assert_eq!(read_c_string(&psinfo[PRPSINFO_FNAME..PRPSINFO_PSARGS]), "a_rather_long_p");
+        assert_eq!(read_c_string(&psinfo[PRPSINFO_PSARGS..]), "./count 3");
+        assert_eq!(read_u32(status, PRSTATUS_PID), Some(100));
+        assert_eq!(read_u32(status, PRSTATUS_PPID), Some(1));
+        assert_eq!(status[PRSTATUS_FPVALID], 1);
+        let loaded: libc::user_regs_struct = read_struct(status, PRSTATUS_REGS).unwrap();
+        assert_eq!((loaded.rip, loaded.rax, loaded.fs_base), (regs.rip, regs.rax, regs.fs_base));
+        let loaded: libc::user_fpregs_struct = read_struct(fp, 0).unwrap();
+        assert_eq!(loaded.mxcsr, 0x1f80);
+    }
+
+    #[test]
+    fn open_bad_cores() {
+        assert!(open("empty", b"").is_err());
+        assert!(open("not_elf", b"#!/bin/sh\n").is_err());
+        // No registers
+        assert!(open("no_notes", &core_bytes(&[], &[])).is_err());
+        // The notes start past the end of the file
+        let mut notes = Vec::new();
+        push_note(&mut notes, NT_PRSTATUS, &vec![0u8; PRSTATUS_SIZE]);
+        let mut bytes = core_bytes(&notes, &[]);
+        let notes_offset = ELF_HEADER_SIZE + 8;
+        bytes[notes_offset..notes_offset + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(open("notes_past_end", &bytes).is_err());
+        // The program headers start past the end of the file
+        let mut bytes = core_bytes(&notes, &[]);
+        bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(open("headers_past_end", &bytes).is_err());
+        // Memory that would run past the top of the address space is left out
+        let bytes = core_bytes(&notes, &[(usize::MAX - 4, b"overflowing")]);
+        let core = open("memory_past_top", &bytes).unwrap();
+        assert!(core.read_bytes(usize::MAX - 2, 1).is_err());
+    }
+}
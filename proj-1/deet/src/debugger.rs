@@ -1,172 +1,2266 @@
-use core::panic;
+use nix::sys::signal::Signal;
 use nix::sys::ptrace;
+use nix::unistd::Pid;
+use crate::core_file::{self, CoreFile};
 use crate::debugger_command::DebuggerCommand;
+use crate::debugger_error::DebuggerError;
 use crate::inferior::Inferior;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use crate::inferior::Status;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::inferior::{ForkPolicy, Status, StopFilter};
+use crate::location::{self, FrameState, Place};
+use crate::dwarf_data::{DwarfData, Encoding, Line, Type, TypeKind, Variable};
+use crate::condition::{Condition, Scalar};
+use crate::disassemble::{self, DecodedInstruction};
+use crate::examine;
+use crate::expression::{self, Context, Evaluator, Value};
+use crate::process::Process;
+use crate::registers;
+use crate::shared_libraries;
+use crate::signals::{self, SignalTable};
+use crate::unwind::Frame;
+use std::collections::HashMap;
+use std::fs;
 pub struct Debugger {
     target: String,
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// Core file being looked at in place of a running inferior; see "--core"
+    core: Option<CoreFile>,
     debug_data:DwarfData,
-    breakpoint:Vec<usize>,
+    breakpoint:Vec<UserBreakpoint>,
+    next_breakpoint_number: usize,
+    /// Stack frame that print and info locals/args look at; 0 is the innermost
+    selected_frame: usize,
+    /// Source file and next line for a bare "list" to continue from
+    list_position: Option<(String, usize)>,
+    /// Last format used by "x", and the address a bare "x" continues from
+    examine_format: examine::Format,
+    examine_position: Option<usize>,
+    /// What to do with each signal the inferior receives; see "handle"
+    signals: SignalTable,
+    /// Number of the current inferior, as "info inferiors" shows it
+    inferior_number: usize,
+    next_inferior_number: usize,
+    /// Processes we're tracing besides the current one, kept stopped until switched to
+    background: Vec<BackgroundInferior>,
+    fork_policy: ForkPolicy,
+    /// (parent, child) inferior numbers while a vforked child still shares its parent's memory
+    vfork: Option<(usize, usize)>,
+}
+
+/// A traced process other than the current one, along with the program it is running.
+struct BackgroundInferior {
+    number: usize,
+    inferior: Inferior,
+    target: String,
+    debug_data: DwarfData,
 }
 #[derive(Clone)]
 pub struct Breakpoint {
     pub addr: usize,
     pub orig_byte: u8,
 }
+
+/// A breakpoint as the user sees it. Several of these may share an address; the inferior only
+/// ever holds a single 0xcc (a `Breakpoint`) per address.
+pub struct UserBreakpoint {
+    pub number: usize,
+    /// The location (or, for a watchpoint, the expression) as the user typed it
+    pub location: String,
+    pub addr: usize,
+    pub enabled: bool,
+    /// Set while the location doesn't resolve in the program being debugged (e.g. after an exec);
+    /// pending breakpoints aren't installed
+    pub pending: bool,
+    pub hit_count: usize,
+    pub condition: Option<Condition>,
+    /// Number of upcoming hits to let pass without stopping
+    pub ignore_count: usize,
+    /// Set if this is a hardware watchpoint on `addr` rather than a breakpoint
+    pub watch: Option<Watch>,
+}
+
+impl UserBreakpoint {
+    /// Returns true if the breakpoint should be installed and can stop the inferior.
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.pending
+    }
+}
+
+/// What a watchpoint is watching, and the values it has seen there.
+pub struct Watch {
+    pub expr: String,
+    pub len: usize,
+    pub entity_type: Type,
+    pub value: Option<Vec<u8>>,
+    /// The value before the most recent change
+    pub old_value: Option<Vec<u8>>,
+}
+
+/// What expressions can see: the variables and registers of one of the program's stack frames.
+struct Scope<'a> {
+    data: &'a DwarfData,
+    process: &'a dyn Process,
+    state: FrameState,
+}
+
+impl<'a> Scope<'a> {
+    fn new(data: &'a DwarfData, process: &'a dyn Process, frame: usize) -> Result<Scope<'a>, String> {
+        Ok(Scope {
+            data,
+            process,
+            state: Debugger::frame_state(data, process, frame)?,
+        })
+    }
+}
+
+impl Context for Scope<'_> {
+    fn variable(&self, name: &str) -> Result<Value, String> {
+        let var = self
+            .data
+            .get_variable(self.state.pc, name)
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+        let place = Debugger::locate(self.data, Some(self.process), Some(&self.state), var)?;
+        let bytes = Debugger::read_place(self.process, Some(&self.state), &place, var.entity_type.size)?;
+        Ok(Value {
+            dtype: var.entity_type.clone(),
+            bytes,
+            place: match place {
+                Place::Memory(_) | Place::Register(_) => Some(place),
+                _ => None,
+            },
+        })
+    }
+
+    fn register(&self, name: &str) -> Result<u64, String> {
+        let regs = self
+            .process
+            .regs()
+            .map_err(|err| format!("Could not read registers: {}", err))?;
+        registers::get(&regs, name).ok_or_else(|| format!("Invalid register \"${}\"", name))
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+        self.process
+            .read_bytes(addr, len)
+            .map_err(|err| format!("Cannot access memory at address {:#x}: {}", addr, err))
+    }
+}
 impl Debugger {
     /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
-        // TODO (milestone 3): initialize the DwarfData
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
-            }
+    pub fn new(target: &str) -> Result<Debugger, DebuggerError> {
+        let debug_data = Self::load_debug_data(target)?;
+        // Without a home directory, keep the history wherever we were started
+        let history_path = match std::env::var("HOME") {
+            Ok(home) => format!("{}/.deet_history", home),
+            Err(_) => ".deet_history".to_string(),
         };
-        let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
         let mut readline = Editor::<()>::new();
         debug_data.print();
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
 
-        Debugger {
+        Ok(Debugger {
             target: target.to_string(),
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data:debug_data,
             breakpoint:Vec::new(),
+            next_breakpoint_number: 0,
+            selected_frame: 0,
+            list_position: None,
+            examine_format: examine::Format::default(),
+            examine_position: None,
+            signals: SignalTable::new(),
+            inferior_number: 1,
+            next_inferior_number: 2,
+            background: Vec::new(),
+            fork_policy: ForkPolicy::default(),
+            vfork: None,
+        })
+    }
+
+    fn load_debug_data(target: &str) -> Result<DwarfData, DebuggerError> {
+        DwarfData::from_file(target).map_err(|err| DebuggerError::DebugInfo(target.to_string(), err))
+    }
+
+    /// Attaches to a running process, debugging whatever executable it is running.
+    pub fn attach(&mut self, pid: &str) -> Result<(), DebuggerError> {
+        let pid = match pid.parse::<i32>() {
+            Ok(pid) if pid > 0 => Pid::from_raw(pid),
+            _ => {
+                return Err(DebuggerError::InvalidArgument(format!(
+                    "Invalid process id \"{}\"",
+                    pid
+                )))
+            }
+        };
+        if let Some(mut inferior) = self.inferior.take() {
+            inferior.kill();
+            self.end_vfork();
+        }
+        let exe = format!("/proc/{}/exe", pid);
+        self.debug_data = Self::load_debug_data(&exe)?;
+        // Remember the real path, so that "run" starts a fresh copy of the same program
+        self.target = match std::fs::read_link(&exe) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(_) => exe,
+        };
+        // Breakpoints go in once we know where the program and its libraries are
+        let mut inferior = Inferior::attach(pid)
+            .map_err(|err| DebuggerError::Ptrace("attach to the process", err))?;
+        inferior.set_signal_table(self.signals.clone());
+        inferior.set_fork_policy(self.fork_policy);
+        println!("Attached to process {}", pid);
+        self.inferior = Some(inferior);
+        self.core = None;
+        self.selected_frame = 0;
+        self.load_shared_libraries();
+        self.relocate_breakpoints()?;
+        self.sync_watchpoints()?;
+        for index in 0..self.breakpoint.len() {
+            self.refresh_watch_value(index);
+        }
+        if let Ok(regs) = ptrace::getregs(pid) {
+            self.print_stop_location(regs.rip as usize);
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebuggerError> {
+        let mut inferior = self.inferior.take().ok_or(DebuggerError::NotRunning)?;
+        inferior
+            .detach()
+            .map_err(|err| DebuggerError::Ptrace("detach from the process", err))?;
+        println!("Detached from process {}", inferior.pid());
+        Ok(())
+    }
+
+    /// Loads a core file to look at in place of a running inferior, and shows where the program
+    /// was when it died.
+    pub fn load_core(&mut self, path: &str) -> Result<(), DebuggerError> {
+        let core = CoreFile::open(path).map_err(|err| DebuggerError::Core(path.to_string(), err))?;
+        if !core.matches_executable(&self.target) {
+            println!("warning: core file may not match specified executable file.");
         }
+        println!("Core was generated by `{}'.", core.command);
+        // Cores saved by "gcore" rather than by the kernel have no signal
+        if let Some(signal) = core.signal {
+            println!(
+                "Program terminated with signal {}, {}.",
+                signal,
+                signals::description(signal)
+            );
+        }
+        if core.num_threads > 1 {
+            println!("[Current thread is 1 (LWP {})]", core.pid);
+        }
+        let rip = core.regs().map_err(|err| DebuggerError::Core(path.to_string(), err.to_string()))?.rip;
+        self.core = Some(core);
+        self.selected_frame = 0;
+        self.list_position = None;
+        self.load_shared_libraries();
+        self.print_stop_location(rip as usize);
+        Ok(())
+    }
+
+    /// Implements "gcore [file]": saves a core file of the inferior as it is now, to core.<pid>
+    /// by default.
+    fn generate_core(&self, file: Option<String>) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?;
+        let path = file.unwrap_or_else(|| format!("core.{}", inferior.pid()));
+        core_file::write(inferior, &path).map_err(|err| DebuggerError::WriteCore(path.clone(), err))?;
+        println!("Saved corefile {}", path);
+        Ok(())
     }
 
     pub fn run(&mut self) {
         loop {
             match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some(){
-                        self.inferior.as_mut().unwrap().kill();
-                    }
-                    if let Some(inferior) = Inferior::new(&self.target, &args,&self.breakpoint) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // TODO (milestone 1): make the inferior run
-                        let status =  self.inferior.as_mut().unwrap().inferior_continue().ok().unwrap();
-                        match status{
-                            Status::Stopped(signal,pointer) => 
-                            {   println!("Child stopped by signal {}",signal);
-                                let file = self.debug_data.get_line_from_addr(pointer).unwrap();
-                                println!("Stopped at {}:{}",file.file,file.number);
-                            },
-                            Status::Exited(exit_code) => {println!("Child exited (status {})",exit_code);
-                                                        self.inferior = None;},
-                            Status::Signaled(signal) => {println!("Child killed by signal {}",signal);
-                                                        self.inferior = None;},
-                            other => panic!("continue return wrong!"),
-                        }
-                        
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                    } else {
-                        println!("Error starting subprocess");
-                    }
-                }
                 DebuggerCommand::Quit => {
-                    self.inferior.as_mut().unwrap().kill();
+                    self.quit();
                     return;
                 }
-                DebuggerCommand::Continue =>{
-                    
-                    if self.inferior.is_none(){
-                        panic!("The process is not run");
+                cmd => {
+                    if let Err(err) = self.execute(cmd) {
+                        println!("{}", err);
                     }
-                    let status =  self.inferior.as_mut().unwrap().inferior_continue().ok().unwrap();
-                    match status{
-                        Status::Exited(exit_code) => {
-                            println!("Child exited (status {})", exit_code);
-                            self.inferior = None;
-                        }
-                        Status::Signaled(signal) => {
-                            println!("Child exited due to signal {}", signal);
-                            self.inferior = None;
-                        }
-                        Status::Stopped(signal, rip) => {
-                            println!("Child stopped (signal {})", signal);
-                            let _line = self.debug_data.get_line_from_addr(rip);
-                            let _func = self.debug_data.get_function_from_addr(rip);
-                            if _line.is_some() && _func.is_some() {
-                                println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
-                            }
-                        }
+                }
+            }
+        }
+    }
+
+    /// Leaves processes we attached to running, like gdb does, and kills ones we started.
+    fn quit(&mut self) {
+        let background = self.background.drain(..).map(|background| background.inferior);
+        for mut inferior in self.inferior.take().into_iter().chain(background) {
+            if !inferior.is_attached() {
+                inferior.kill();
+                continue;
+            }
+            match inferior.detach() {
+                Ok(()) => println!("Detached from process {}", inferior.pid()),
+                Err(err) => println!("{}", DebuggerError::Ptrace("detach from the process", err)),
+            }
+        }
+    }
+
+    fn execute(&mut self, cmd: DebuggerCommand) -> Result<(), DebuggerError> {
+        match cmd {
+            DebuggerCommand::Quit => {
+                self.quit();
+                Ok(())
+            }
+            DebuggerCommand::Run(args) => {
+                if let Some(mut inferior) = self.inferior.take() {
+                    inferior.kill();
+                    self.end_vfork();
+                }
+                // Breakpoints go in once we know where the program and its libraries are
+                let mut inferior = Inferior::new(&self.target, &args)
+                    .map_err(|err| DebuggerError::Spawn(self.target.clone(), err))?;
+                inferior.set_signal_table(self.signals.clone());
+                inferior.set_fork_policy(self.fork_policy);
+                self.inferior = Some(inferior);
+                self.core = None;
+                self.load_shared_libraries();
+                self.relocate_breakpoints()?;
+                self.sync_watchpoints()?;
+                for index in 0..self.breakpoint.len() {
+                    self.refresh_watch_value(index);
+                }
+                self.resume(false, |inferior, _, should_stop| {
+                    inferior.inferior_continue(should_stop)
+                })
+            }
+            DebuggerCommand::Attach(pid) => self.attach(&pid),
+            DebuggerCommand::Detach => self.detach(),
+            DebuggerCommand::Gcore(file) => self.generate_core(file),
+            DebuggerCommand::Continue => self.resume(false, |inferior, _, should_stop| {
+                inferior.inferior_continue(should_stop)
+            }),
+            DebuggerCommand::Backtrace => self.print_backtrace(),
+            DebuggerCommand::Break(arg) => self.set_breakpoint(&arg),
+            DebuggerCommand::Watch(expr) => self.set_watchpoint(&expr),
+            DebuggerCommand::Up(count) => self.move_frame(count, 1),
+            DebuggerCommand::Down(count) => self.move_frame(count, -1),
+            DebuggerCommand::Frame(level) => self.select_frame(level),
+            DebuggerCommand::InfoBreakpoints => {
+                self.print_breakpoints();
+                Ok(())
+            }
+            DebuggerCommand::InfoLocals => self.print_frame_variables(false),
+            DebuggerCommand::InfoRegisters(names) => self.print_registers(&names),
+            DebuggerCommand::Set(args) => self.set(&args),
+            DebuggerCommand::InfoArgs => self.print_frame_variables(true),
+            DebuggerCommand::InfoSignals(signal) => self.print_signals(signal),
+            DebuggerCommand::Handle(args) => self.handle_signals(&args),
+            DebuggerCommand::Signal(signal) => self.continue_with_signal(&signal),
+            DebuggerCommand::InfoInferiors => {
+                self.print_inferiors();
+                Ok(())
+            }
+            DebuggerCommand::Inferior(number) => self.switch_inferior(number),
+            DebuggerCommand::InfoThreads => self.print_threads(),
+            DebuggerCommand::Thread(number) => self.switch_thread(number),
+            DebuggerCommand::InfoSharedLibrary => {
+                self.print_shared_libraries();
+                Ok(())
+            }
+            DebuggerCommand::Delete(args) => self.delete_breakpoints(&args),
+            DebuggerCommand::Ignore(args) => self.ignore_breakpoint(&args),
+            DebuggerCommand::Enable(args) => self.set_breakpoints_enabled(&args, true),
+            DebuggerCommand::Disable(args) => self.set_breakpoints_enabled(&args, false),
+            DebuggerCommand::Step => self.resume(true, |inferior, data, should_stop| {
+                inferior.step_line(data, true, should_stop)
+            }),
+            DebuggerCommand::Next => self.resume(true, |inferior, data, should_stop| {
+                inferior.step_line(data, false, should_stop)
+            }),
+            DebuggerCommand::StepInstruction(count) => self.step_instructions(count, false),
+            DebuggerCommand::NextInstruction(count) => self.step_instructions(count, true),
+            DebuggerCommand::Finish => self.finish(),
+            DebuggerCommand::List(location) => self.list_source(location),
+            DebuggerCommand::Examine(spec, expr) => self.examine(spec, &expr),
+            DebuggerCommand::Disassemble(args) => self.disassemble(&args),
+            DebuggerCommand::Print(expr) if expr.is_empty() => {
+                Err(DebuggerError::Usage("print <expression>"))
+            }
+            DebuggerCommand::Print(expr) => self.print_expression(&expr),
+            DebuggerCommand::Ptype(name) if name.is_empty() => {
+                Err(DebuggerError::Usage("ptype <expression or type>"))
+            }
+            DebuggerCommand::Ptype(name) => self.print_type(&name),
+        }
+    }
+
+    /// Resumes the inferior with `action`, which should stop only at breakpoints approved by the
+    /// stop filter it is given, then reports where the inferior ended up. Forks and execs are
+    /// dealt with along the way; after one, a continue carries on (in whichever process we now
+    /// follow) but a step stops, since the line it was stepping is gone or in another process.
+    fn resume<F>(&mut self, stepping: bool, mut action: F) -> Result<(), DebuggerError>
+    where
+        F: FnMut(&mut Inferior, &DwarfData, &mut StopFilter) -> Result<Status, nix::Error>,
+    {
+        let mut hits = Vec::new();
+        let tid_before = self.inferior.as_ref().map(Inferior::tid);
+        let status = loop {
+            if let Some((parent, child)) = self.vfork {
+                if parent == self.inferior_number {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "Can not resume the parent process over vfork while holding the child \
+                         stopped. Let the child run first with \"inferior {}\".",
+                        child
+                    )));
+                }
+            }
+            // An exec brings in another dynamic linker
+            let library_event = self.debug_data.library_event_address();
+            let inferior = self.inferior.as_mut().ok_or(DebuggerError::NotRunning)?;
+            let breakpoints = &mut self.breakpoint;
+            let data = &self.debug_data;
+            let status = action(inferior, data, &mut |inferior, addr| {
+                // The dynamic linker has loaded or unloaded libraries. Steps don't stop for it,
+                // since the libraries are looked at again whenever the inferior stops anyway.
+                if Some(addr) == library_event {
+                    return !stepping;
+                }
+                Self::should_stop_at(breakpoints, data, inferior, addr, &mut hits)
+            })
+            .map_err(|err| DebuggerError::Ptrace("resume the inferior", err))?;
+            match status {
+                Status::Forked(child, vfork) => self.follow_fork(*child, vfork)?,
+                Status::Execed(_) => self.follow_exec()?,
+                Status::Stopped(Signal::SIGTRAP, rip) if Some(rip) == library_event && !stepping => {
+                    if self.load_shared_libraries() {
+                        self.relocate_breakpoints()?;
                     }
+                    continue;
                 }
-                DebuggerCommand::Backtrace =>{
-                    if self.inferior.is_some(){              
-                        self.inferior.as_ref().unwrap().print_backtrace(&self.debug_data);
-                    }else{
-                        panic!("doesn't have a running process");
+                status => break status,
+            }
+            if stepping {
+                let tid = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?.tid();
+                let regs = ptrace::getregs(tid)
+                    .map_err(|err| DebuggerError::Ptrace("read registers", err))?;
+                break Status::Stopped(Signal::SIGTRAP, regs.rip as usize);
+            }
+        };
+        if self.inferior.is_some() && status.is_alive() && self.load_shared_libraries() {
+            self.relocate_breakpoints()?;
+        }
+        if let Some(inferior) = self.inferior.as_ref() {
+            if inferior.threads().len() > 1 && tid_before != Some(inferior.tid()) {
+                let thread = inferior.current_thread();
+                println!("[Switching to thread {} (LWP {})]", thread.number, thread.tid);
+            }
+        }
+        self.report_status(status, stepping, &hits);
+        Ok(())
+    }
+
+    /// Deals with the current inferior forking a child we have been asked to follow or keep (a
+    /// child we simply let go is detached by Inferior). The process we don't follow stays stopped
+    /// in the background, unless detach-on-fork says to let it go.
+    fn follow_fork(&mut self, mut child: Inferior, vfork: bool) -> Result<(), DebuggerError> {
+        // Temporary breakpoints the parent has since taken out are still in the child's copy
+        let wanted = self.enabled_breakpoint_addrs();
+        let stale: Vec<usize> = child
+            .breakpoint
+            .keys()
+            .filter(|addr| !wanted.contains(addr))
+            .cloned()
+            .collect();
+        for addr in stale {
+            child
+                .remove_breakpoint(addr)
+                .map_err(|err| DebuggerError::Memory(addr, err))?;
+        }
+        // The child is running the same program as its parent, loaded at the same place
+        let mut debug_data = Self::load_debug_data(&self.target)?;
+        debug_data.set_load_bias(self.debug_data.load_bias());
+        debug_data.set_libraries(&self.debug_data.libraries());
+        let child_pid = child.pid();
+        let child = BackgroundInferior {
+            number: self.next_inferior_number,
+            inferior: child,
+            target: self.target.clone(),
+            debug_data,
+        };
+        self.next_inferior_number += 1;
+        let parent_number = self.inferior_number;
+        if vfork {
+            self.vfork = Some((parent_number, child.number));
+        }
+        if !self.fork_policy.follow_child {
+            println!("[New inferior {} (process {})]", child.number, child_pid);
+            self.background.push(child);
+            return Ok(());
+        }
+        let parent_pid = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?.pid();
+        println!(
+            "[Attaching after process {} {} to child process {}]",
+            parent_pid,
+            if vfork { "vfork" } else { "fork" },
+            child_pid
+        );
+        self.make_current(child);
+        // A vfork parent shares the child's memory, so it has to wait for the child to exec or exit
+        if !vfork && self.fork_policy.detach {
+            self.detach_background(parent_number)?;
+        }
+        self.sync_watchpoints()
+    }
+
+    /// Picks up the new program after the current inferior calls exec, and sets the breakpoints
+    /// again in it.
+    fn follow_exec(&mut self) -> Result<(), DebuggerError> {
+        let pid = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?.pid();
+        let exe = format!("/proc/{}/exe", pid);
+        let target = match fs::read_link(&exe) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(_) => exe,
+        };
+        println!("process {} is executing new program: {}", pid, target);
+        self.end_vfork();
+        self.debug_data = Self::load_debug_data(&target)?;
+        self.target = target;
+        self.load_shared_libraries();
+        self.reset_breakpoints()
+    }
+
+    /// Called when the current inferior execs, exits or is replaced. Once a vforked child does
+    /// that it no longer shares its parent's memory, so a parent we only held on to for its sake
+    /// can be let go.
+    fn end_vfork(&mut self) {
+        match self.vfork {
+            Some((parent, child)) if child == self.inferior_number => {
+                self.vfork = None;
+                if self.fork_policy.follow_child && self.fork_policy.detach {
+                    if let Err(err) = self.detach_background(parent) {
+                        println!("{}", err);
                     }
                 }
-                DebuggerCommand::Break(arg) =>{
-                    if arg.starts_with('*'){
-                        let point = &arg[1..];
-                        let mut address = Self::parse_address(point);
-                        println!("Set breakpoint {} at {:#x}",self.breakpoint.len(),address.unwrap());
-                        self.breakpoint.push(address.unwrap());
-                        if self.inferior.is_some(){
-                            match self.inferior.as_mut().unwrap().write_byte(address.unwrap(), 0xcc){
-                                Ok(orig_byte) => { self.inferior.as_mut().unwrap().breakpoint.insert(address.unwrap(), Breakpoint { addr: address.unwrap(), orig_byte: orig_byte });}
-                                Err(_) => println!("Invalid breakpoint address {:#x}",address.unwrap()),
-                            }
-                            
+            }
+            Some((parent, _)) if parent == self.inferior_number => self.vfork = None,
+            _ => {}
+        }
+    }
+
+    fn detach_background(&mut self, number: usize) -> Result<(), DebuggerError> {
+        let index = match self.background.iter().position(|bg| bg.number == number) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let mut parent = self.background.remove(index).inferior;
+        parent
+            .detach()
+            .map_err(|err| DebuggerError::Ptrace("detach from the process", err))?;
+        println!("[Detaching from parent process {}]", parent.pid());
+        Ok(())
+    }
+
+    /// Makes `next` the current inferior, moving the current one (if it's still alive) into the
+    /// background.
+    fn make_current(&mut self, next: BackgroundInferior) {
+        let target = std::mem::replace(&mut self.target, next.target);
+        let debug_data = std::mem::replace(&mut self.debug_data, next.debug_data);
+        if let Some(inferior) = self.inferior.replace(next.inferior) {
+            self.background.push(BackgroundInferior {
+                number: self.inferior_number,
+                inferior,
+                target,
+                debug_data,
+            });
+        }
+        self.inferior_number = next.number;
+        self.selected_frame = 0;
+        self.list_position = None;
+    }
+
+    /// Resolves every breakpoint again against the current program, after it has changed (an
+    /// exec, or switching to an inferior running something else), and installs them afresh.
+    /// Watchpoints, which watch the old program's memory, are disabled.
+    fn reset_breakpoints(&mut self) -> Result<(), DebuggerError> {
+        if let Some(inferior) = self.inferior.as_mut() {
+            let addrs: Vec<usize> = inferior.breakpoint.keys().cloned().collect();
+            for addr in addrs {
+                inferior
+                    .remove_breakpoint(addr)
+                    .map_err(|err| DebuggerError::Memory(addr, err))?;
+            }
+        }
+        for bp in self.breakpoint.iter_mut().filter(|bp| bp.watch.is_some() && bp.enabled) {
+            println!("Watchpoint {} disabled: it watched another program.", bp.number);
+            bp.enabled = false;
+        }
+        self.relocate_breakpoints()?;
+        self.sync_watchpoints()
+    }
+
+    /// Resolves the breakpoints again after the program's addresses may have changed (it was
+    /// started, exec'd or loaded libraries), moving them in the inferior to match. Breakpoints
+    /// that can't be resolved are left pending until they can.
+    fn relocate_breakpoints(&mut self) -> Result<(), DebuggerError> {
+        let before = self.enabled_breakpoint_addrs();
+        for index in 0..self.breakpoint.len() {
+            if self.breakpoint[index].watch.is_some() {
+                continue;
+            }
+            match self.resolve_location(&self.breakpoint[index].location) {
+                Ok(addr) => {
+                    self.breakpoint[index].addr = addr;
+                    self.breakpoint[index].pending = false;
+                }
+                Err(_) => self.breakpoint[index].pending = true,
+            }
+        }
+        let after = self.enabled_breakpoint_addrs();
+        for addr in before.into_iter().chain(after) {
+            self.sync_breakpoint(addr)?;
+        }
+        Ok(())
+    }
+
+    /// Works out where the program and its shared libraries are in the current inferior or core
+    /// file, so that addresses in the debugging information match those in memory. Returns true
+    /// if anything moved, or libraries came or went.
+    fn load_shared_libraries(&mut self) -> bool {
+        let before = (self.debug_data.load_bias(), self.debug_data.libraries());
+        let load_bias = self
+            .process()
+            .and_then(|process| shared_libraries::load_bias(process, &self.debug_data));
+        if let Some(load_bias) = load_bias {
+            self.debug_data.set_load_bias(load_bias);
+        }
+        let libraries = match self.process() {
+            Some(process) => shared_libraries::libraries(process, &self.debug_data),
+            None => return false,
+        };
+        self.debug_data.set_libraries(&libraries);
+        before != (self.debug_data.load_bias(), self.debug_data.libraries())
+    }
+
+    /// Lists the shared libraries the program has loaded, like gdb's "info sharedlibrary".
+    fn print_shared_libraries(&self) {
+        let libraries = self.debug_data.describe_libraries();
+        if libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("{:<20}{:<20}{:<12}Shared Object Library", "From", "To", "Syms Read");
+        for (path, start, end, symbols) in &libraries {
+            let read = match symbols {
+                Some(true) => "Yes",
+                Some(false) => "Yes (*)",
+                None => "No",
+            };
+            println!("{:<#20x}{:<#20x}{:<12}{}", start, end, read, path);
+        }
+        if libraries.iter().any(|library| library.3 == Some(false)) {
+            println!("(*): Shared library is missing debugging information.");
+        }
+    }
+
+    /// Lists the current inferior's threads and where each one is, like gdb's "info threads".
+    fn print_threads(&self) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?;
+        println!("  {:<5}{:<18}Frame", "Id", "Target Id");
+        for thread in inferior.threads() {
+            let marker = if thread.tid == inferior.tid() { "*" } else { " " };
+            let frame = match ptrace::getregs(thread.tid) {
+                Ok(regs) => {
+                    let rip = regs.rip as usize;
+                    format!("{:#x} {}", rip, self.describe_location(rip))
+                }
+                Err(err) => format!("<unavailable: {}>", err),
+            };
+            println!("{} {:<5}{:<18}{}", marker, thread.number, format!("LWP {}", thread.tid), frame);
+        }
+        Ok(())
+    }
+
+    /// Switches to another thread: "thread 2". Without a number, says which is current.
+    fn switch_thread(&mut self, number: Option<String>) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_mut().ok_or(DebuggerError::NotRunning)?;
+        let arg = match number {
+            Some(arg) => arg,
+            None => {
+                let thread = inferior.current_thread();
+                println!("[Current thread is {} (LWP {})]", thread.number, thread.tid);
+                return Ok(());
+            }
+        };
+        let found = match arg.parse::<usize>() {
+            Ok(number) => inferior.select_thread(number),
+            Err(_) => false,
+        };
+        if !found {
+            return Err(DebuggerError::InvalidArgument(format!("Invalid thread ID: {}", arg)));
+        }
+        let thread = inferior.current_thread();
+        println!("[Switching to thread {} (LWP {})]", thread.number, thread.tid);
+        let rip = ptrace::getregs(thread.tid)
+            .map_err(|err| DebuggerError::Ptrace("read registers", err))?
+            .rip as usize;
+        self.selected_frame = 0;
+        self.list_position = None;
+        self.print_stop_location(rip);
+        Ok(())
+    }
+
+    /// Lists the current inferior and the ones in the background, like gdb's "info inferiors".
+    fn print_inferiors(&self) {
+        let mut rows: Vec<(usize, Option<&Inferior>, &str)> = self
+            .background
+            .iter()
+            .map(|bg| (bg.number, Some(&bg.inferior), bg.target.as_str()))
+            .collect();
+        rows.push((self.inferior_number, self.inferior.as_ref(), self.target.as_str()));
+        rows.sort_by_key(|row| row.0);
+        println!("  {:<5}{:<18}Executable", "Num", "Description");
+        for (number, inferior, target) in rows {
+            let description = match inferior {
+                Some(inferior) => format!("process {}", inferior.pid()),
+                None => "<null>".to_string(),
+            };
+            let marker = if number == self.inferior_number { "*" } else { " " };
+            println!("{} {:<5}{:<18}{}", marker, number, description, target);
+        }
+    }
+
+    /// Switches to another traced process: "inferior 2". Without a number, says which is current.
+    fn switch_inferior(&mut self, number: Option<String>) -> Result<(), DebuggerError> {
+        let number = match number {
+            Some(arg) => arg.parse::<usize>().map_err(|_| {
+                DebuggerError::InvalidArgument(format!("Invalid inferior number \"{}\"", arg))
+            })?,
+            None => self.inferior_number,
+        };
+        if number != self.inferior_number {
+            let index = self
+                .background
+                .iter()
+                .position(|bg| bg.number == number)
+                .ok_or_else(|| {
+                    DebuggerError::InvalidArgument(format!("No inferior number {}.", number))
+                })?;
+            let next = self.background.remove(index);
+            let changed_program = next.target != self.target;
+            self.make_current(next);
+            if changed_program {
+                self.reset_breakpoints()?;
+            }
+            self.sync_watchpoints()?;
+        }
+        let (pid, tid) = match self.inferior.as_ref() {
+            Some(inferior) => (inferior.pid(), inferior.tid()),
+            None => {
+                println!("[Current inferior is {} [<null>] ({})]", number, self.target);
+                return Ok(());
+            }
+        };
+        println!("[Switching to inferior {} [process {}] ({})]", number, pid, self.target);
+        if let Ok(regs) = ptrace::getregs(tid) {
+            self.print_stop_location(regs.rip as usize);
+        }
+        Ok(())
+    }
+
+    /// Decides whether to stop at the breakpoint (or watchpoint) at `addr`, updating hit and
+    /// ignore counts of the user breakpoints there. Breakpoints that stop are added to `hits`.
+    fn should_stop_at(
+        breakpoints: &mut [UserBreakpoint],
+        data: &DwarfData,
+        inferior: &Inferior,
+        addr: usize,
+        hits: &mut Vec<usize>,
+    ) -> bool {
+        let mut user_breakpoint = false;
+        let mut stop = false;
+        for bp in breakpoints.iter_mut().filter(|bp| bp.is_active() && bp.addr == addr) {
+            user_breakpoint = true;
+            if let Some(watch) = bp.watch.as_mut() {
+                // Writes that leave the value as it was aren't interesting
+                let value = inferior.read_bytes(addr, watch.len).ok();
+                if value == watch.value {
+                    continue;
+                }
+                watch.old_value = std::mem::replace(&mut watch.value, value);
+            }
+            if let Some(condition) = &bp.condition {
+                let result = Scope::new(data, inferior, 0)
+                    .and_then(|scope| condition.evaluate(data, &scope));
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    // Stop so the user can sort it out
+                    Err(err) => println!(
+                        "Error in testing condition for breakpoint {}: {}",
+                        bp.number, err
+                    ),
+                }
+            }
+            bp.hit_count += 1;
+            if bp.ignore_count > 0 {
+                bp.ignore_count -= 1;
+                continue;
+            }
+            hits.push(bp.number);
+            stop = true;
+        }
+        // Anything else trapping here is a temporary breakpoint used for stepping
+        stop || !user_breakpoint
+    }
+
+    /// Reports how the inferior stopped after it was resumed, and forgets about it if it
+    /// terminated. When `stepping`, the SIGTRAP that ends every step isn't worth mentioning.
+    fn report_status(&mut self, status: Status, stepping: bool, hits: &[usize]) {
+        self.selected_frame = 0;
+        self.list_position = None;
+        match status {
+            Status::Exited(exit_code) => {
+                println!("Child exited (status {})", exit_code);
+                self.inferior = None;
+                self.end_vfork();
+            }
+            Status::Signaled(signal) => {
+                println!("Child exited due to signal {}", signal);
+                self.inferior = None;
+                self.end_vfork();
+            }
+            // resume deals with these before reporting
+            Status::Forked(..) | Status::Execed(_) => {}
+            Status::Watchpoint(_, rip) => {
+                for bp in self.breakpoint.iter().filter(|bp| hits.contains(&bp.number)) {
+                    let watch = match &bp.watch {
+                        Some(watch) => watch,
+                        None => continue,
+                    };
+                    let format = |value: &Option<Vec<u8>>| match value {
+                        Some(bytes) => self.debug_data.format_value(&watch.entity_type, bytes),
+                        None => "<unknown>".to_string(),
+                    };
+                    println!("Hardware watchpoint {}: {}", bp.number, watch.expr);
+                    println!();
+                    println!("Old value = {}", format(&watch.old_value));
+                    println!("New value = {}", format(&watch.value));
+                }
+                self.print_stop_location(rip);
+            }
+            Status::Stopped(signal, rip) => {
+                if !stepping || signal != Signal::SIGTRAP {
+                    println!("Child stopped (signal {})", signal);
+                }
+                for number in hits {
+                    println!("Hit breakpoint {}", number);
+                }
+                self.print_stop_location(rip);
+            }
+        }
+    }
+
+    /// Reports where the inferior stopped, including the exact pc when it's partway through a
+    /// line (e.g. after stepi or finish).
+    fn print_stop_location(&self, rip: usize) {
+        let line = self.debug_data.get_line_from_addr(rip);
+        let func = self.debug_data.get_function_from_addr(rip);
+        match (func, line) {
+            (Some(func), Some(line)) if !self.debug_data.is_line_start(rip) => {
+                println!("Stopped at {:#x} in {} ({})", rip, func, line)
+            }
+            (Some(func), Some(line)) => println!("Stopped at {} ({})", func, line),
+            (Some(func), None) => println!("Stopped at {:#x} in {}", rip, func),
+            _ => println!("Stopped at {:#x}", rip),
+        }
+    }
+
+    fn print_backtrace(&self) -> Result<(), DebuggerError> {
+        for (index, frame) in self.stack_frames()?.iter().enumerate() {
+            println!("{}", Self::describe_frame(index, frame));
+        }
+        Ok(())
+    }
+
+    fn describe_frame(index: usize, frame: &Frame) -> String {
+        match (&frame.function, &frame.line) {
+            (Some(func), Some(line)) => format!("#{:<3}{} ({})", index, func, line),
+            (Some(func), None) => format!("#{:<3}{:#x} in {}", index, frame.pc, func),
+            _ => format!("#{:<3}{:#x} in ??", index, frame.pc),
+        }
+    }
+
+    /// The running inferior or, failing that, the core file, for commands that only look at the
+    /// program.
+    fn process(&self) -> Option<&dyn Process> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior),
+            (None, Some(core)) => Some(core),
+            (None, None) => None,
+        }
+    }
+
+    /// Unwinds the program's stack.
+    fn stack_frames(&self) -> Result<Vec<Frame>, DebuggerError> {
+        let process = self.process().ok_or(DebuggerError::NoStack)?;
+        process
+            .backtrace(&self.debug_data)
+            .map_err(|err| DebuggerError::Ptrace("read the stack", err))
+    }
+
+    /// Implements "frame [n]": selects frame n, or describes the selected frame.
+    fn select_frame(&mut self, level: Option<String>) -> Result<(), DebuggerError> {
+        let frames = self.stack_frames()?;
+        if let Some(level) = level {
+            match level.parse::<usize>() {
+                Ok(level) if level < frames.len() => {
+                    self.selected_frame = level;
+                    self.list_position = None;
+                }
+                Ok(_) => {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "No frame at level {}.",
+                        level
+                    )))
+                }
+                Err(_) => {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "Invalid frame number \"{}\"",
+                        level
+                    )))
+                }
+            }
+        }
+        if let Some(frame) = frames.get(self.selected_frame) {
+            println!("{}", Self::describe_frame(self.selected_frame, frame));
+        }
+        Ok(())
+    }
+
+    /// Implements "up [n]" (direction 1, towards callers) and "down [n]" (direction -1).
+    fn move_frame(&mut self, count: Option<String>, direction: isize) -> Result<(), DebuggerError> {
+        let count = match count {
+            None => 1,
+            Some(count) => count.parse::<isize>().map_err(|_| {
+                DebuggerError::InvalidArgument(format!("Invalid count \"{}\"", count))
+            })?,
+        };
+        let frames = self.stack_frames()?;
+        let level = self.selected_frame as isize + direction * count;
+        if level < 0 {
+            return Err(DebuggerError::InvalidArgument(
+                "Bottom (innermost) frame selected; you cannot go down.".to_string(),
+            ));
+        }
+        if level as usize >= frames.len() {
+            return Err(DebuggerError::InvalidArgument(
+                "Initial frame selected; you cannot go up.".to_string(),
+            ));
+        }
+        self.selected_frame = level as usize;
+        self.list_position = None;
+        println!("{}", Self::describe_frame(self.selected_frame, &frames[self.selected_frame]));
+        Ok(())
+    }
+
+    /// Implements "info locals" and "info args", printing the selected frame's local variables or
+    /// parameters.
+    fn print_frame_variables(&self, parameters: bool) -> Result<(), DebuggerError> {
+        let process = self.process().ok_or(DebuggerError::NoFrame)?;
+        let state = Self::frame_state(&self.debug_data, process, self.selected_frame)?;
+        let func = self
+            .debug_data
+            .get_function_for_addr(state.pc)
+            .ok_or("No symbol table info available.")?;
+        let mut found = false;
+        for var in func.variables.iter().filter(|var| var.is_parameter == parameters) {
+            found = true;
+            let value = Self::locate(&self.debug_data, Some(process), Some(&state), var)
+                .and_then(|place| Self::read_place(process, Some(&state), &place, var.entity_type.size));
+            match value {
+                Ok(Some(bytes)) => println!("{} = {}", var.name, self.debug_data.format_value(&var.entity_type, &bytes)),
+                Ok(None) => println!("{} = <optimized out>", var.name),
+                Err(err) => println!("{} = <{}>", var.name, err),
+            }
+        }
+        if !found {
+            println!("{}", if parameters { "No arguments." } else { "No locals." });
+        }
+        Ok(())
+    }
+
+    /// Returns the source line the selected frame is at, if there is a running inferior or a core.
+    fn selected_line(&self) -> Option<Line> {
+        let process = self.process()?;
+        let state = Self::frame_state(&self.debug_data, process, self.selected_frame).ok()?;
+        self.debug_data.get_line_from_addr(state.pc)
+    }
+
+    /// Returns the line the selected frame is at, or the start of main before the program runs.
+    fn default_line(&self) -> Option<Line> {
+        self.selected_line().or_else(|| {
+            let addr = self.debug_data.get_addr_for_function(None, "main")?;
+            self.debug_data.get_line_from_addr(addr)
+        })
+    }
+
+    /// Implements "list", "list <line>", "list <func>", "list <file>:<line>" and
+    /// "list <file>:<func>". A bare "list" continues where the last one left off, or lists around
+    /// the selected frame (or main, before the program runs).
+    fn list_source(&mut self, location: Option<String>) -> Result<(), DebuggerError> {
+        const LINES: usize = 10;
+        let current_file = || {
+            self.list_position
+                .as_ref()
+                .map(|(file, _)| file.clone())
+                .or_else(|| self.default_line().map(|line| line.file))
+        };
+        let (file, first) = match location {
+            None => match self.list_position.clone() {
+                Some(position) => position,
+                None => {
+                    let line = self.default_line().ok_or(DebuggerError::NoSourceFile)?;
+                    (line.file, line.number.saturating_sub(LINES / 2).max(1))
+                }
+            },
+            Some(location) => {
+                let (file, spec) = match location.rfind(':') {
+                    Some(pos) => match self.debug_data.get_source_path(&location[..pos]) {
+                        Some(path) => (Some(path), &location[pos + 1..]),
+                        None => {
+                            return Err(DebuggerError::InvalidArgument(format!(
+                                "No source file named {}.",
+                                &location[..pos]
+                            )))
                         }
-                    }else{
-                        
-                        let line = Self::parse_address(arg.as_str());
-                        match line{
-                            Some(number) => {
-                                
-                                let address = self.debug_data.get_addr_for_line(None, arg.parse::<usize>().ok().unwrap());
-                                println!("Set breakpoint {} at {:#x}",self.breakpoint.len(),address.unwrap());
-                                self.breakpoint.push(address.unwrap());
-                                if self.inferior.is_some(){
-                                    match self.inferior.as_mut().unwrap().write_byte(address.unwrap(), 0xcc){
-                                        Ok(orig_byte) => { self.inferior.as_mut().unwrap().breakpoint.insert(address.unwrap(), Breakpoint { addr: address.unwrap(), orig_byte: orig_byte });}
-                                        Err(_) => println!("Invalid breakpoint address {:#x}",address.unwrap()),
-                                    }
-                                    
-                                }
-                            },
-                            None => {
-                                let address = self.debug_data.get_addr_for_function(None, &arg);
-                                println!("Set breakpoint {} at {:#x}",self.breakpoint.len(),address.unwrap());
-                                self.breakpoint.push(address.unwrap());
-                                if self.inferior.is_some(){
-                                    match self.inferior.as_mut().unwrap().write_byte(address.unwrap(), 0xcc){
-                                        Ok(orig_byte) => { self.inferior.as_mut().unwrap().breakpoint.insert(address.unwrap(), Breakpoint { addr: address.unwrap(), orig_byte: orig_byte });}
-                                        Err(_) => println!("Invalid breakpoint address {:#x}",address.unwrap()),
-                                    }
-                                    
-                                }
-                            },
+                    },
+                    None => (None, &location[..]),
+                };
+                let line = match spec.parse::<usize>() {
+                    Ok(number) => match file.or_else(current_file) {
+                        Some(file) => Line { file, number, address: 0 },
+                        None => return Err(DebuggerError::NoSourceFile),
+                    },
+                    Err(_) => {
+                        let filename = location.rfind(':').map(|pos| &location[..pos]);
+                        let line = self
+                            .debug_data
+                            .get_addr_for_function(filename, spec)
+                            .and_then(|addr| self.debug_data.get_line_from_addr(addr));
+                        line.ok_or_else(|| {
+                            DebuggerError::InvalidArgument(format!(
+                                "Function \"{}\" not defined.",
+                                spec
+                            ))
+                        })?
+                    }
+                };
+                (line.file, line.number.saturating_sub(LINES / 2).max(1))
+            }
+        };
+
+        let source = fs::read_to_string(&file).map_err(|err| DebuggerError::Source(file.clone(), err))?;
+        let lines: Vec<&str> = source.lines().collect();
+        if first > lines.len() {
+            return Err(DebuggerError::InvalidArgument(format!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first,
+                file,
+                lines.len()
+            )));
+        }
+        let current = self.selected_line().filter(|line| line.file == file);
+        let breakpoint_lines: Vec<usize> = self
+            .breakpoint
+            .iter()
+            .filter(|bp| bp.is_active() && bp.watch.is_none())
+            .filter_map(|bp| self.debug_data.get_line_from_addr(bp.addr))
+            .filter(|line| line.file == file)
+            .map(|line| line.number)
+            .collect();
+        let last = (first + LINES - 1).min(lines.len());
+        for number in first..=last {
+            let bp_mark = if breakpoint_lines.contains(&number) { 'b' } else { ' ' };
+            let current_mark = match &current {
+                Some(line) if line.number == number => '>',
+                _ => ' ',
+            };
+            println!("{}{} {:<5} {}", bp_mark, current_mark, number, lines[number - 1]);
+        }
+        self.list_position = Some((file, last + 1));
+        Ok(())
+    }
+
+    /// Describes where an address is in the source, e.g. "in main at samples/count.c:4".
+    fn describe_location(&self, addr: usize) -> String {
+        let func = self.debug_data.get_function_from_addr(addr);
+        let line = self.debug_data.get_line_from_addr(addr);
+        match (func, line) {
+            (Some(func), Some(line)) => format!("in {} at {}", func, line),
+            (Some(func), None) => format!("in {}", func),
+            (None, Some(line)) => format!("at {}", line),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn enabled_breakpoint_addrs(&self) -> Vec<usize> {
+        let mut addrs: Vec<usize> = Vec::new();
+        for bp in self.breakpoint.iter().filter(|bp| bp.is_active() && bp.watch.is_none()) {
+            if !addrs.contains(&bp.addr) {
+                addrs.push(bp.addr);
+            }
+        }
+        // Not one of the user's, but where the dynamic linker reports loading libraries
+        if let Some(addr) = self.debug_data.library_event_address() {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    /// Makes the inferior's 0xcc at `addr` match the enabled breakpoints: installs it if any
+    /// enabled breakpoint is there, and restores the original byte otherwise.
+    fn sync_breakpoint(&mut self, addr: usize) -> Result<(), DebuggerError> {
+        let wanted = self.enabled_breakpoint_addrs().contains(&addr);
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return Ok(()),
+        };
+        let result = if wanted {
+            inferior.insert_breakpoint(addr)
+        } else {
+            inferior.remove_breakpoint(addr)
+        };
+        result.map_err(|err| DebuggerError::Memory(addr, err))
+    }
+
+    fn set_breakpoint(&mut self, args: &str) -> Result<(), DebuggerError> {
+        let (arg, condition) = match args.find(" if ") {
+            Some(pos) => (args[..pos].trim(), Some(&args[pos + 4..])),
+            None => (args.trim(), None),
+        };
+        if arg.is_empty() {
+            return Err(DebuggerError::Usage("break <location> [if <condition>]"));
+        }
+        let condition = match condition {
+            Some(text) => Some(Condition::parse(text, &self.debug_data).map_err(|err| {
+                DebuggerError::InvalidArgument(format!("Invalid breakpoint condition: {}", err))
+            })?),
+            None => None,
+        };
+        // A function or file that isn't in the program may turn up in a library it loads later
+        let (addr, pending) = match self.resolve_location(arg) {
+            Ok(addr) => (addr, false),
+            Err(err @ DebuggerError::NotDefined(_)) if self.debug_data.interpreter().is_some() => {
+                println!("{}", err);
+                (0, true)
+            }
+            Err(err) => return Err(err),
+        };
+        let number = self.next_breakpoint_number;
+        self.next_breakpoint_number += 1;
+        if pending {
+            println!("Breakpoint {} ({}) pending.", number, arg);
+        } else {
+            println!("Set breakpoint {} at {:#x}", number, addr);
+        }
+        self.breakpoint.push(UserBreakpoint {
+            number,
+            location: arg.to_string(),
+            addr,
+            enabled: true,
+            pending,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+            watch: None,
+        });
+        self.sync_breakpoint(addr)
+    }
+
+    /// Finds the address for a breakpoint location: `*<addr>`, `<line>`, `<func>`,
+    /// `<file>:<line>` or `<file>:<func>`. Bare line numbers are in the current source file.
+    fn resolve_location(&self, arg: &str) -> Result<usize, DebuggerError> {
+        let invalid = |message: String| DebuggerError::InvalidArgument(message);
+        if arg.starts_with('*') {
+            return Self::parse_address(&arg[1..])
+                .ok_or_else(|| invalid(format!("Invalid address \"{}\"", &arg[1..])));
+        }
+        let (file, spec) = match arg.rfind(':') {
+            Some(pos) => (Some(&arg[..pos]), &arg[pos + 1..]),
+            None => (None, arg),
+        };
+        let file = match file {
+            Some(file) => {
+                let files = self.debug_data.find_files(file);
+                match files.len() {
+                    0 => {
+                        return Err(DebuggerError::NotDefined(format!("No source file named {}.", file)))
+                    }
+                    1 => Some(files[0].name.clone()),
+                    _ => {
+                        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+                        return Err(invalid(format!(
+                            "\"{}\" matches several files ({}); use a longer path",
+                            file,
+                            names.join(", ")
+                        )));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        if let Ok(line_number) = spec.parse::<usize>() {
+            let file = file.or_else(|| {
+                let line = self.default_line()?;
+                let files = self.debug_data.find_files(&line.file);
+                files.first().map(|f| f.name.clone())
+            });
+            return self
+                .debug_data
+                .get_addr_for_line(file.as_deref(), line_number)
+                .ok_or_else(|| {
+                    invalid(match &file {
+                        Some(file) => {
+                            format!("No code at or after line {} in \"{}\".", line_number, file)
                         }
-                        
-                        
+                        None => format!("No code at or after line {}.", line_number),
+                    })
+                });
+        }
+        let funcs = self.debug_data.get_functions_named(file.as_deref(), spec);
+        match funcs.len() {
+            0 => match file {
+                // Maybe a function without debugging information, such as one in libc
+                None => self.debug_data.get_addr_for_function(None, spec),
+                Some(_) => None,
+            }
+            .ok_or_else(|| {
+                DebuggerError::NotDefined(format!("Function \"{}\" not defined.", spec))
+            }),
+            1 => Ok(funcs[0].1.address),
+            _ => {
+                let names: Vec<&str> = funcs.iter().map(|(f, _)| f.name.as_str()).collect();
+                Err(invalid(format!(
+                    "Function \"{}\" is defined in several files ({}); use <file>:{}",
+                    spec,
+                    names.join(", "),
+                    spec
+                )))
+            }
+        }
+    }
+
+    /// Points the inferior's debug registers at the enabled watchpoints.
+    fn sync_watchpoints(&mut self) -> Result<(), DebuggerError> {
+        let mut watches: Vec<(usize, usize)> = Vec::new();
+        for bp in self.breakpoint.iter().filter(|bp| bp.enabled) {
+            if let Some(watch) = &bp.watch {
+                if !watches.contains(&(bp.addr, watch.len)) {
+                    watches.push((bp.addr, watch.len));
+                }
+            }
+        }
+        match self.inferior.as_mut() {
+            Some(inferior) => inferior
+                .set_watchpoints(&watches)
+                .map_err(|err| DebuggerError::Ptrace("set hardware watchpoints", err)),
+            None => Ok(()),
+        }
+    }
+
+    /// Re-reads the value watched by the watchpoint at `index`, so the next change can be
+    /// reported against it.
+    fn refresh_watch_value(&mut self, index: usize) {
+        let bp = &mut self.breakpoint[index];
+        if let (Some(watch), Some(inferior)) = (bp.watch.as_mut(), self.inferior.as_ref()) {
+            watch.value = inferior.read_bytes(bp.addr, watch.len).ok();
+        }
+    }
+
+    fn set_watchpoint(&mut self, expr: &str) -> Result<(), DebuggerError> {
+        if expr.is_empty() {
+            return Err(DebuggerError::Usage("watch <expression>"));
+        }
+        // "*<number>" watches raw memory; any other expression watches the value it refers to
+        let raw_addr = if expr.starts_with('*') {
+            Self::parse_address(&expr[1..])
+        } else {
+            None
+        };
+        let (addr, len, entity_type) = if let Some(addr) = raw_addr {
+            // Watch as much of the word at addr as alignment allows
+            let len = [8, 4, 2, 1].iter().cloned().find(|len| addr % len == 0).unwrap_or(1);
+            (
+                addr,
+                len,
+                Type::new(format!("unsigned {}-byte", len), len, TypeKind::Base(Encoding::Unsigned)),
+            )
+        } else {
+            match self.evaluate(expr)? {
+                Value {
+                    place: Some(Place::Memory(addr)),
+                    dtype,
+                    ..
+                } => (addr, dtype.size, dtype),
+                Value {
+                    place: Some(Place::Register(register)),
+                    ..
+                } => {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "Cannot watch \"{}\", which is in register ${}",
+                        expr,
+                        registers::dwarf_name(register)
+                    )))
+                }
+                _ => {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "Cannot watch constant value `{}'.",
+                        expr
+                    )))
+                }
+            }
+        };
+        if ![1, 2, 4, 8].contains(&len) || addr % len != 0 {
+            return Err(DebuggerError::InvalidArgument(format!(
+                "Cannot watch \"{}\": hardware watchpoints need an aligned 1, 2, 4 or 8 byte value",
+                expr
+            )));
+        }
+        let in_use = self
+            .breakpoint
+            .iter()
+            .filter(|bp| bp.enabled && bp.watch.is_some())
+            .count();
+        if in_use >= 4 {
+            return Err(DebuggerError::InvalidArgument(
+                "Cannot set watchpoint: all 4 hardware debug registers are in use.".to_string(),
+            ));
+        }
+        let number = self.next_breakpoint_number;
+        self.next_breakpoint_number += 1;
+        println!("Hardware watchpoint {}: {}", number, expr);
+        self.breakpoint.push(UserBreakpoint {
+            number,
+            location: expr.to_string(),
+            addr,
+            enabled: true,
+            pending: false,
+            hit_count: 0,
+            condition: None,
+            ignore_count: 0,
+            watch: Some(Watch {
+                expr: expr.to_string(),
+                len,
+                entity_type,
+                value: None,
+                old_value: None,
+            }),
+        });
+        self.refresh_watch_value(self.breakpoint.len() - 1);
+        self.sync_watchpoints()
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoint.is_empty() {
+            println!("No breakpoints.");
+            return;
+        }
+        println!(
+            "{:<8}{:<15}{:<4}{:<19}{:<6}What",
+            "Num", "Type", "Enb", "Address", "Hits"
+        );
+        for bp in &self.breakpoint {
+            let (kind, what) = match &bp.watch {
+                Some(watch) => ("hw watchpoint", watch.expr.clone()),
+                None if bp.pending => ("breakpoint", bp.location.clone()),
+                None => ("breakpoint", self.describe_location(bp.addr)),
+            };
+            let address = if bp.pending {
+                "<PENDING>".to_string()
+            } else {
+                format!("{:#x}", bp.addr)
+            };
+            println!(
+                "{:<8}{:<15}{:<4}{:<19}{:<6}{}",
+                bp.number,
+                kind,
+                if bp.enabled { "y" } else { "n" },
+                address,
+                bp.hit_count,
+                what
+            );
+            if let Some(condition) = &bp.condition {
+                println!("\tstop only if {}", condition);
+            }
+            match bp.ignore_count {
+                0 => {}
+                1 => println!("\tWill ignore next crossing of breakpoint."),
+                count => println!("\tWill ignore next {} crossings of breakpoint.", count),
+            }
+        }
+    }
+
+    /// Resolves breakpoint numbers given on the command line to indices into `self.breakpoint`.
+    /// No numbers at all means every breakpoint.
+    fn find_breakpoints(&self, args: &[String]) -> Result<Vec<usize>, DebuggerError> {
+        if args.is_empty() {
+            return Ok((0..self.breakpoint.len()).collect());
+        }
+        let mut indices = Vec::new();
+        for arg in args {
+            let number = arg.parse::<usize>().map_err(|_| {
+                DebuggerError::InvalidArgument(format!("Bad breakpoint number \"{}\"", arg))
+            })?;
+            match self.breakpoint.iter().position(|bp| bp.number == number) {
+                Some(index) => indices.push(index),
+                None => {
+                    return Err(DebuggerError::InvalidArgument(format!(
+                        "No breakpoint number {}.",
+                        number
+                    )))
+                }
+            }
+        }
+        Ok(indices)
+    }
+
+    fn ignore_breakpoint(&mut self, args: &[String]) -> Result<(), DebuggerError> {
+        if args.len() != 2 {
+            return Err(DebuggerError::Usage("ignore <breakpoint number> <count>"));
+        }
+        let index = self.find_breakpoints(&args[..1])?[0];
+        let count = args[1].parse::<usize>().map_err(|_| {
+            DebuggerError::InvalidArgument(format!("Bad ignore count \"{}\"", args[1]))
+        })?;
+        let bp = &mut self.breakpoint[index];
+        bp.ignore_count = count;
+        match count {
+            0 => println!("Will stop next time breakpoint {} is reached.", bp.number),
+            1 => println!("Will ignore next crossing of breakpoint {}.", bp.number),
+            _ => println!("Will ignore next {} crossings of breakpoint {}.", count, bp.number),
+        }
+        Ok(())
+    }
+
+    fn delete_breakpoints(&mut self, args: &[String]) -> Result<(), DebuggerError> {
+        let mut indices = self.find_breakpoints(args)?;
+        // Remove from the back so the remaining indices stay valid
+        indices.sort_unstable();
+        indices.dedup();
+        for index in indices.into_iter().rev() {
+            let bp = self.breakpoint.remove(index);
+            if bp.watch.is_some() {
+                self.sync_watchpoints()?;
+            } else {
+                self.sync_breakpoint(bp.addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_breakpoints_enabled(&mut self, args: &[String], enabled: bool) -> Result<(), DebuggerError> {
+        for index in self.find_breakpoints(args)? {
+            self.breakpoint[index].enabled = enabled;
+            if self.breakpoint[index].watch.is_some() {
+                self.sync_watchpoints()?;
+            } else {
+                let addr = self.breakpoint[index].addr;
+                self.sync_breakpoint(addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the policy for one signal, or for all of them, in the format of gdb's "info signals".
+    fn print_signals(&self, signal: Option<String>) -> Result<(), DebuggerError> {
+        let selected = match signal {
+            Some(name) => vec![signals::parse(&name).map_err(DebuggerError::InvalidArgument)?],
+            None => Signal::iterator().collect(),
+        };
+        println!("Signal        Stop\tPrint\tPass to program\tDescription");
+        for signal in selected {
+            let policy = self.signals.policy(signal);
+            let yes_no = |flag| if flag { "Yes" } else { "No" };
+            println!(
+                "{:<14}{}\t{}\t{}\t\t{}",
+                signal.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                signals::description(signal)
+            );
+        }
+        Ok(())
+    }
+
+    /// Changes how signals are handled, e.g. "handle SIGUSR1 nostop noprint" or
+    /// "handle all nopass", then shows the new policy.
+    fn handle_signals(&mut self, args: &[String]) -> Result<(), DebuggerError> {
+        const USAGE: &str = "handle SIGNAL|all [stop|nostop|print|noprint|pass|nopass]...";
+        let (name, keywords) = args.split_first().ok_or(DebuggerError::Usage(USAGE))?;
+        let selected: Vec<Signal> = if name == "all" {
+            // Like gdb, "all" leaves the signals the debugger itself relies on alone
+            Signal::iterator()
+                .filter(|&signal| signal != Signal::SIGTRAP && signal != Signal::SIGINT)
+                .collect()
+        } else {
+            vec![signals::parse(name).map_err(DebuggerError::InvalidArgument)?]
+        };
+        for keyword in keywords {
+            for &signal in &selected {
+                self.signals
+                    .apply(signal, keyword)
+                    .map_err(DebuggerError::InvalidArgument)?;
+            }
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_signal_table(self.signals.clone());
+        }
+        if name == "all" {
+            return self.print_signals(None);
+        }
+        self.print_signals(Some(name.clone()))
+    }
+
+    /// Resumes the inferior delivering the given signal instead of the one it stopped with;
+    /// "signal 0" resumes without one.
+    fn continue_with_signal(&mut self, name: &str) -> Result<(), DebuggerError> {
+        if name.is_empty() {
+            return Err(DebuggerError::Usage("signal SIGNAL"));
+        }
+        let signal = match name {
+            "0" => None,
+            _ => Some(signals::parse(name).map_err(DebuggerError::InvalidArgument)?),
+        };
+        let inferior = self.inferior.as_mut().ok_or(DebuggerError::NotRunning)?;
+        inferior.set_pending_signal(signal);
+        match signal {
+            Some(signal) => println!("Continuing with signal {}.", signal),
+            None => println!("Continuing with no signal."),
+        }
+        self.resume(false, |inferior, _, should_stop| {
+            inferior.inferior_continue(should_stop)
+        })
+    }
+
+    /// Implements "stepi [n]" and, with `step_over`, "nexti [n]".
+    fn step_instructions(&mut self, count: Option<String>, step_over: bool) -> Result<(), DebuggerError> {
+        let count = match count {
+            None => 1,
+            Some(count) => count.parse::<usize>().map_err(|_| {
+                DebuggerError::InvalidArgument(format!("Invalid instruction count \"{}\"", count))
+            })?,
+        };
+        self.resume(true, |inferior, _, should_stop| {
+            inferior.step_instructions(count, step_over, should_stop)
+        })
+    }
+
+    /// Runs until the current function returns, then prints the value it returned.
+    fn finish(&mut self) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?;
+        let rip = ptrace::getregs(inferior.tid())
+            .map_err(|err| DebuggerError::Ptrace("read registers", err))?
+            .rip as usize;
+        let func = self.debug_data.get_function_for_addr(rip).cloned();
+        if let Some(func) = &func {
+            if func.name == "main" {
+                return Err(DebuggerError::InvalidArgument(
+                    "\"finish\" not meaningful in the outermost frame.".to_string(),
+                ));
+            }
+            println!("Run till exit from {}", func.name);
+        }
+        let mut ret_addr = None;
+        self.resume(true, |inferior, data, should_stop| {
+            let (addr, cfa) = inferior.frame_return_address(data)?;
+            ret_addr = Some(addr);
+            inferior.run_to_frame(addr, cfa, should_stop)
+        })?;
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => return Ok(()),
+        };
+        let returned = match ptrace::getregs(inferior.tid()) {
+            Ok(regs) => Some(regs.rip as usize) == ret_addr,
+            Err(_) => false,
+        };
+        if !returned {
+            return Ok(());
+        }
+        let return_type = match func.and_then(|func| func.return_type) {
+            Some(return_type) => return_type,
+            // void function, or one we know nothing about
+            None => return Ok(()),
+        };
+        let resolved = self.debug_data.resolve_alias(&return_type);
+        match resolved.kind {
+            TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => {
+                return self.print_returned_aggregate(inferior, &return_type)
+            }
+            _ => {}
+        }
+        let bytes = if return_type.is_float() {
+            inferior.fpregs().map(|fpregs| {
+                // xmm0 is the first 16 bytes of xmm_space
+                let low = fpregs.xmm_space[0] as u64 | (fpregs.xmm_space[1] as u64) << 32;
+                low.to_le_bytes().to_vec()
+            })
+        } else {
+            ptrace::getregs(inferior.tid()).map(|regs| regs.rax.to_le_bytes().to_vec())
+        };
+        let bytes = bytes.map_err(|err| DebuggerError::Ptrace("read the return value", err))?;
+        println!(
+            "Value returned is {}",
+            return_type.format_value(&bytes[..return_type.size.min(8)])
+        );
+        Ok(())
+    }
+
+    /// Prints a struct or union just returned by a function. As the x86-64 calling convention
+    /// has it, those of up to 16 bytes come back a word at a time in rax and rdx, except that words
+    /// holding only floating point fields come back in xmm0 and xmm1. Larger ones are returned
+    /// in memory, which the caller may have reused already.
+    fn print_returned_aggregate(&self, inferior: &Inferior, return_type: &Type) -> Result<(), DebuggerError> {
+        let size = self.debug_data.resolve_alias(return_type).size;
+        if size > 16 {
+            println!("Value returned has type: {}. Cannot determine contents", return_type.name);
+            return Ok(());
+        }
+        let ptrace_err = |err| DebuggerError::Ptrace("read the return value", err);
+        let fields = self.debug_data.scalar_fields(return_type);
+        let is_sse: Vec<bool> = (0..size)
+            .step_by(8)
+            .map(|word| {
+                let mut in_word = fields.iter().filter(|(offset, _)| offset / 8 == word / 8).peekable();
+                in_word.peek().is_some() && in_word.all(|(_, dtype)| dtype.is_float())
+            })
+            .collect();
+        let regs = ptrace::getregs(inferior.tid()).map_err(ptrace_err)?;
+        let mut integer = vec![regs.rax, regs.rdx].into_iter();
+        let mut sse = Vec::new();
+        if is_sse.contains(&true) {
+            // The low halves of xmm0 and xmm1
+            let xmm = inferior.fpregs().map_err(ptrace_err)?.xmm_space;
+            let low = |i: usize| xmm[i] as u64 | (xmm[i + 1] as u64) << 32;
+            sse = vec![low(0), low(4)];
+        }
+        let mut sse = sse.into_iter();
+        let mut bytes = Vec::new();
+        for is_sse in is_sse {
+            let word = if is_sse { sse.next() } else { integer.next() };
+            bytes.extend_from_slice(&word.unwrap_or(0).to_le_bytes());
+        }
+        println!(
+            "Value returned is {}",
+            self.debug_data.format_value(return_type, &bytes[..size])
+        );
+        Ok(())
+    }
+
+    /// Describes the registers of the stack frame at the given level (0 being the innermost), for
+    /// scoping variable lookups and finding where variables are.
+    fn frame_state(data: &DwarfData, process: &dyn Process, frame: usize) -> Result<FrameState, String> {
+        let frames = process
+            .backtrace(data)
+            .map_err(|err| format!("Error reading stack: {}", err))?;
+        let selected = frames
+            .get(frame)
+            .ok_or_else(|| format!("No frame at level {}.", frame))?;
+        // Vector registers aren't saved across calls, so they're only known in the innermost frame
+        let vector_registers = match process.fpregs() {
+            Ok(fpregs) if frame == 0 => fpregs
+                .xmm_space
+                .chunks(4)
+                .map(|words| {
+                    let mut bytes = [0u8; 16];
+                    for (i, word) in words.iter().enumerate() {
+                        bytes[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+                    }
+                    bytes
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(FrameState {
+            // Callers are stopped just after a call, which may be past the end of their scope
+            pc: if frame == 0 { selected.pc } else { selected.pc - 1 },
+            cfa: selected.cfa,
+            registers: selected.registers,
+            vector_registers,
+        })
+    }
+
+    /// Works out where a variable is in the frame described by `state`.
+    fn locate(
+        data: &DwarfData,
+        process: Option<&dyn Process>,
+        state: Option<&FrameState>,
+        var: &Variable,
+    ) -> Result<Place, String> {
+        let func = state.and_then(|state| data.get_function_for_addr(state.pc));
+        let read_memory =
+            |addr: usize, len: usize| process.and_then(|process| process.read_bytes(addr, len).ok());
+        location::locate(&var.location, var.entity_type.size, func, state, &read_memory)
+    }
+
+    /// Reads the value at `place`. Returns None if the value has been optimized out.
+    fn read_place(
+        process: &dyn Process,
+        state: Option<&FrameState>,
+        place: &Place,
+        size: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        match place {
+            Place::Memory(addr) => process
+                .read_bytes(*addr, size)
+                .map(Some)
+                .map_err(|err| format!("Cannot access memory at address {:#x}: {}", addr, err)),
+            Place::Register(register) => state
+                .ok_or("No frame selected.")?
+                .read_register(*register, size)
+                .map(Some),
+            Place::Value(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                Ok(Some(bytes))
+            }
+            Place::OptimizedOut => Ok(None),
+        }
+    }
+
+    /// Evaluates a C expression in the selected frame.
+    fn evaluate(&self, text: &str) -> Result<Value, String> {
+        let process = self.process().ok_or("The program is not being run.")?;
+        let expr = expression::parse(text, &self.debug_data)?;
+        let scope = Scope::new(&self.debug_data, process, self.selected_frame)?;
+        Evaluator::new(&self.debug_data, &scope).evaluate(&expr)
+    }
+
+    /// Evaluates a C expression in the selected frame as a number. Arrays and functions stand for
+    /// their address.
+    fn evaluate_scalar(&self, text: &str) -> Result<Scalar, String> {
+        let process = self.process().ok_or("The program is not being run.")?;
+        let expr = expression::parse(text, &self.debug_data)?;
+        let scope = Scope::new(&self.debug_data, process, self.selected_frame)?;
+        let evaluator = Evaluator::new(&self.debug_data, &scope);
+        evaluator.scalar(&evaluator.evaluate(&expr)?)
+    }
+
+    /// Shows the type of an expression in the selected frame, or the definition of a named type
+    /// such as "struct point".
+    fn print_type(&self, text: &str) -> Result<(), DebuggerError> {
+        if let Some(dtype) = self.debug_data.get_type_by_name(text) {
+            println!("type = {}", self.debug_data.describe_type(dtype));
+            return Ok(());
+        }
+        // Globals can be looked at before the program runs
+        let dtype = match (self.process(), self.debug_data.get_variable(0, text)) {
+            (None, Some(var)) => var.entity_type.clone(),
+            (None, None) => {
+                return Err(DebuggerError::InvalidArgument(format!(
+                    "No symbol \"{}\" in current context.",
+                    text
+                )))
+            }
+            _ => self.evaluate(text)?.dtype,
+        };
+        println!("type = {}", self.debug_data.describe_type(&dtype));
+        Ok(())
+    }
+
+    fn print_expression(&self, text: &str) -> Result<(), DebuggerError> {
+        let process = self.process().ok_or(DebuggerError::NotRunning)?;
+        // A lone register is shown in its most useful form, e.g. eflags as flags
+        let is_register = text.starts_with('$')
+            && text[1..].chars().all(|c| c.is_alphanumeric() || c == '_');
+        if is_register {
+            let regs = process
+                .regs()
+                .map_err(|err| DebuggerError::Ptrace("read registers", err))?;
+            let value = registers::get(&regs, &text[1..]).ok_or_else(|| {
+                DebuggerError::InvalidArgument(format!("Invalid register \"{}\"", text))
+            })?;
+            println!("{} = {}", text, Self::format_register(&text[1..], value));
+            return Ok(());
+        }
+        match self.evaluate(text)? {
+            Value { bytes: Some(bytes), dtype, .. } => {
+                println!("{} = {}", text, self.debug_data.format_value(&dtype, &bytes))
+            }
+            Value { bytes: None, .. } => println!("{} = <optimized out>", text),
+        }
+        Ok(())
+    }
+
+    /// Evaluates an address for "x", "disassemble" and "set *<addr>": any expression whose value
+    /// is a number or pointer.
+    fn eval_address(&self, text: &str) -> Result<usize, String> {
+        match self.evaluate_scalar(text)? {
+            Scalar::Int(value) => Ok(value as usize),
+            Scalar::Float(_) => Err(format!("\"{}\" is not an address", text)),
+        }
+    }
+
+    /// Labels an address with the symbol it falls in, e.g. "0x404028 <global+4>".
+    fn label_address(&self, addr: usize) -> String {
+        match self.debug_data.get_symbol_for_addr(addr) {
+            Some((name, 0)) => format!("{:#x} <{}>", addr, name),
+            Some((name, offset)) => format!("{:#x} <{}+{}>", addr, name, offset),
+            None => format!("{:#x}", addr),
+        }
+    }
+
+    /// Implements "x/<count><format><size> <address>". Without an address, continues after the
+    /// memory shown last time.
+    fn examine(&mut self, spec: Option<String>, expr: &str) -> Result<(), DebuggerError> {
+        let format = match spec {
+            Some(spec) => examine::Format::parse(&spec, &self.examine_format)
+                .map_err(DebuggerError::InvalidArgument)?,
+            None => examine::Format {
+                count: 1,
+                ..self.examine_format
+            },
+        };
+        let addr = if expr.trim().is_empty() {
+            self.examine_position.ok_or_else(|| {
+                DebuggerError::InvalidArgument(
+                    "Argument required (starting display address).".to_string(),
+                )
+            })?
+        } else {
+            self.eval_address(expr)?
+        };
+        self.examine_format = format;
+        let process = self.process().ok_or(DebuggerError::NotRunning)?;
+
+        let mut addr = addr;
+        if format.letter == 'i' {
+            let pc = process.regs().ok().map(|regs| regs.rip as usize);
+            let (instructions, _) = Self::decode_code(process, addr, usize::MAX, Some(format.count));
+            self.print_instructions(&instructions, pc, None, false);
+            match instructions.last() {
+                Some(last) if instructions.len() == format.count => {
+                    self.examine_position = Some(last.addr + last.len);
+                }
+                Some(last) => println!("Cannot access memory at address {:#x}", last.addr + last.len),
+                None => println!("Cannot access memory at address {:#x}", addr),
+            }
+            return Ok(());
+        }
+        if format.letter == 's' {
+            for _ in 0..format.count {
+                match Self::read_string(process, addr) {
+                    Ok(bytes) => {
+                        println!("{}:\t{}", self.label_address(addr), examine::quote_string(&bytes));
+                        addr += bytes.len() + 1;
+                    }
+                    Err(_) => {
+                        println!("Cannot access memory at address {:#x}", addr);
+                        break;
                     }
-                    
                 }
             }
+            self.examine_position = Some(addr);
+            return Ok(());
+        }
+
+        let per_line = format.units_per_line();
+        let mut remaining = format.count;
+        while remaining > 0 {
+            let units = remaining.min(per_line);
+            let bytes = match process.read_bytes(addr, units * format.size) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    println!("Cannot access memory at address {:#x}", addr);
+                    break;
+                }
+            };
+            let values: Vec<String> = bytes
+                .chunks(format.size)
+                .map(|unit| format.format_unit(unit))
+                .collect();
+            if format.letter == 'x' && format.size == 1 {
+                // Hex dump, with the bytes as text alongside
+                println!(
+                    "{}:  {:<width$}  |{}|",
+                    self.label_address(addr),
+                    values.join(" "),
+                    examine::ascii_gutter(&bytes),
+                    width = 3 * per_line - 1
+                );
+            } else {
+                println!("{}:\t{}", self.label_address(addr), values.join("\t"));
+            }
+            addr += bytes.len();
+            remaining -= units;
+        }
+        self.examine_position = Some(addr);
+        Ok(())
+    }
+
+    /// Decodes the instructions from `addr` up to `end`, stopping after `count` of them if given.
+    /// The code is read a page at a time, so a huge range or count only reads as far as there is
+    /// memory; if some of it can't be read, returns what came before along with the error.
+    fn decode_code(
+        process: &dyn Process,
+        addr: usize,
+        end: usize,
+        count: Option<usize>,
+    ) -> (Vec<DecodedInstruction>, Option<DebuggerError>) {
+        const PAGE_SIZE: usize = 4096;
+        let mut instructions = Vec::new();
+        let mut next = addr;
+        while next < end && count != Some(instructions.len()) {
+            // The rest of the page, and enough of the next for an instruction that runs into it
+            let page_left = PAGE_SIZE - next % PAGE_SIZE;
+            let len = (page_left + disassemble::MAX_INSTRUCTION_LEN).min(end - next);
+            // The next page may not be readable (e.g. at the end of the text segment)
+            let bytes = match process
+                .read_code(next, len)
+                .or_else(|_| process.read_code(next, len.min(page_left)))
+            {
+                Ok(bytes) => bytes,
+                Err(err) => return (instructions, Some(DebuggerError::Memory(next, err))),
+            };
+            let remaining = count.map(|count| count - instructions.len());
+            let decoded = disassemble::decode(&bytes, next, remaining);
+            match decoded.last() {
+                Some(last) => next = last.addr + last.len,
+                None => break,
+            }
+            instructions.extend(decoded);
+        }
+        (instructions, None)
+    }
+
+    /// Implements "disassemble", "disassemble <func or addr>" and "disassemble <start>,<end>"
+    /// (or "<start>,+<length>"). Without arguments, disassembles the selected frame's function.
+    fn disassemble(&mut self, args: &str) -> Result<(), DebuggerError> {
+        let process = self.process().ok_or(DebuggerError::NotRunning)?;
+        let (start, end, func_name) = if let Some(comma) = args.find(',') {
+            let start = self.eval_address(&args[..comma])?;
+            let end = args[comma + 1..].trim();
+            let end = if end.starts_with('+') {
+                let len = self.eval_address(&end[1..])?;
+                start.checked_add(len).ok_or_else(|| {
+                    DebuggerError::InvalidArgument(format!(
+                        "Invalid address range {:#x},+{:#x}",
+                        start, len
+                    ))
+                })?
+            } else {
+                self.eval_address(end)?
+            };
+            (start, end, None)
+        } else {
+            let addr = if args.trim().is_empty() {
+                Self::frame_state(&self.debug_data, process, self.selected_frame)?.pc
+            } else {
+                self.eval_address(args)?
+            };
+            let func = self.debug_data.get_function_for_addr(addr).ok_or_else(|| {
+                DebuggerError::InvalidArgument("No function contains specified address.".to_string())
+            })?;
+            let (low, high) = func.text_range();
+            (low, high, Some(func.name.clone()))
+        };
+        if end <= start {
+            return Err(DebuggerError::InvalidArgument(format!(
+                "Invalid address range {:#x},{:#x}",
+                start, end
+            )));
+        }
+        let pc = process.regs().ok().map(|regs| regs.rip as usize);
+        let (instructions, err) = Self::decode_code(process, start, end, None);
+        let err = match err {
+            Some(err) if instructions.is_empty() => return Err(err),
+            err => err,
+        };
+        match &func_name {
+            Some(name) => println!("Dump of assembler code for function {}:", name),
+            None => println!("Dump of assembler code from {:#x} to {:#x}:", start, end),
+        }
+        let relative_to = func_name.map(|_| start);
+        self.print_instructions(&instructions, pc, relative_to, true);
+        // Like gdb, show what could be read before saying what couldn't
+        if let Some(err) = err {
+            return Err(err);
         }
+        println!("End of assembler dump.");
+        Ok(())
+    }
+
+    /// Prints decoded instructions, marking the one at `pc`. Instructions are labeled by their
+    /// offset from `relative_to` if given, and by symbol otherwise. With `annotate`, each source
+    /// line is shown above the code generated for it.
+    fn print_instructions(
+        &self,
+        instructions: &[DecodedInstruction],
+        pc: Option<usize>,
+        relative_to: Option<usize>,
+        annotate: bool,
+    ) {
+        let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+        let mut last_line: Option<Line> = None;
+        for instruction in instructions {
+            if annotate {
+                let line = self.debug_data.get_line_from_addr(instruction.addr);
+                let changed = match (&line, &last_line) {
+                    (Some(line), Some(last)) => line.file != last.file || line.number != last.number,
+                    (Some(_), None) => true,
+                    _ => false,
+                };
+                if let (true, Some(line)) = (changed, &line) {
+                    let source = sources
+                        .entry(line.file.clone())
+                        .or_insert_with(|| match fs::read_to_string(&line.file) {
+                            Ok(text) => text.lines().map(|text| text.to_string()).collect(),
+                            Err(_) => Vec::new(),
+                        })
+                        .get(line.number.wrapping_sub(1))
+                        .map_or("", |text| text.as_str());
+                    println!("{}\t{}", line, source);
+                }
+                last_line = line;
+            }
+            let marker = if Some(instruction.addr) == pc { "=> " } else { "   " };
+            let label = match relative_to {
+                Some(start) => format!("{:#x} <+{}>", instruction.addr, instruction.addr - start),
+                None => self.label_address(instruction.addr),
+            };
+            let target = match instruction
+                .branch_target
+                .and_then(|target| self.debug_data.get_symbol_for_addr(target))
+            {
+                Some((name, 0)) => format!(" <{}>", name),
+                Some((name, offset)) => format!(" <{}+{}>", name, offset),
+                None => String::new(),
+            };
+            println!("{}{}:\t{}{}", marker, label, instruction.text, target);
+        }
+    }
+
+    /// Reads a NUL-terminated string (without the NUL), giving up after 4096 bytes.
+    fn read_string(process: &dyn Process, addr: usize) -> Result<Vec<u8>, nix::Error> {
+        const PAGE_SIZE: usize = 4096;
+        let mut string = Vec::new();
+        let mut next = addr;
+        while string.len() < PAGE_SIZE {
+            // Don't read across a page boundary, which may be the end of mapped memory
+            let chunk = process.read_bytes(next, PAGE_SIZE - next % PAGE_SIZE)?;
+            match chunk.iter().position(|byte| *byte == 0) {
+                Some(end) => {
+                    string.extend_from_slice(&chunk[..end]);
+                    return Ok(string);
+                }
+                None => string.extend_from_slice(&chunk),
+            }
+            next += chunk.len();
+        }
+        string.truncate(PAGE_SIZE);
+        Ok(string)
+    }
+
+    /// Shows a register value the way "print" does: addresses in hex, eflags as flags, and
+    /// everything else as a signed number.
+    fn format_register(name: &str, value: u64) -> String {
+        if name == "eflags" {
+            registers::describe_eflags(value)
+        } else if registers::is_pointer(name) {
+            format!("{:#x}", value)
+        } else {
+            (value as i64).to_string()
+        }
+    }
+
+    /// Implements "info registers [name...]".
+    fn print_registers(&self, names: &[String]) -> Result<(), DebuggerError> {
+        let process = self.process().ok_or(DebuggerError::NotRunning)?;
+        let regs = process
+            .regs()
+            .map_err(|err| DebuggerError::Ptrace("read registers", err))?;
+        let names: Vec<&str> = if names.is_empty() {
+            registers::NAMES.to_vec()
+        } else {
+            names.iter().map(|name| name.trim_start_matches('$')).collect()
+        };
+        for name in names {
+            let value = match registers::get(&regs, name) {
+                Some(value) => value,
+                None => {
+                    println!("Invalid register \"${}\"", name);
+                    continue;
+                }
+            };
+            let natural = if name == "rip" || name == "pc" {
+                self.describe_location(value as usize)
+            } else {
+                Self::format_register(name, value)
+            };
+            println!("{:<10} {:<#20x} {}", name, value, natural);
+        }
+        Ok(())
+    }
+
+    /// Implements "set $<register> = <value>", "set var <variable> = <value>" and
+    /// "set *<address> = <byte> [<byte>...]".
+    fn set(&mut self, args: &str) -> Result<(), DebuggerError> {
+        const USAGE: &str =
+            "set $<register> = <value> | set var <variable> = <value> | set *<address> = <bytes>";
+        let mut words = args.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("follow-fork-mode"), Some(mode), None) => return self.set_follow_fork_mode(mode),
+            (Some("detach-on-fork"), Some(setting), None) => return self.set_detach_on_fork(setting),
+            _ => {}
+        }
+        let (target, value) = match args.find('=') {
+            Some(pos) => (args[..pos].trim(), args[pos + 1..].trim()),
+            None => return Err(DebuggerError::Usage(USAGE)),
+        };
+        if target.starts_with('$') {
+            self.set_register(&target[1..], value)
+        } else if target.starts_with('*') {
+            self.set_memory(&target[1..], value)
+        } else if target.starts_with("var ") {
+            self.set_variable(target["var ".len()..].trim(), value)
+        } else if !target.is_empty() && !value.is_empty() {
+            self.set_variable(target, value)
+        } else {
+            Err(DebuggerError::Usage(USAGE))
+        }
+    }
+
+    /// Implements "set follow-fork-mode parent|child": which process to debug after a fork.
+    fn set_follow_fork_mode(&mut self, mode: &str) -> Result<(), DebuggerError> {
+        self.fork_policy.follow_child = match mode {
+            "parent" => false,
+            "child" => true,
+            _ => return Err(DebuggerError::Usage("set follow-fork-mode parent|child")),
+        };
+        self.update_fork_policy();
+        Ok(())
+    }
+
+    /// Implements "set detach-on-fork on|off": whether to let go of the process not followed.
+    fn set_detach_on_fork(&mut self, setting: &str) -> Result<(), DebuggerError> {
+        self.fork_policy.detach = match setting {
+            "on" => true,
+            "off" => false,
+            _ => return Err(DebuggerError::Usage("set detach-on-fork on|off")),
+        };
+        self.update_fork_policy();
+        Ok(())
+    }
+
+    /// Hands the fork policy to every inferior, since each deals with its own forks.
+    fn update_fork_policy(&mut self) {
+        let background = self.background.iter_mut().map(|bg| &mut bg.inferior);
+        for inferior in self.inferior.iter_mut().chain(background) {
+            inferior.set_fork_policy(self.fork_policy);
+        }
+    }
+
+    /// Assigns the value of an expression to a variable, or anything else assignable such as
+    /// `*p`, `arr[2]` or `s.field`.
+    fn set_variable(&mut self, name: &str, value: &str) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?;
+        let target = self.evaluate(name)?;
+        let dtype = self.debug_data.resolve_alias(&target.dtype);
+        let cannot_assign = |why: String| {
+            DebuggerError::InvalidArgument(format!("Cannot assign to \"{}\"{}", name, why))
+        };
+        if !dtype.is_scalar() {
+            return Err(cannot_assign(format!(" of type {}", target.dtype.name)));
+        }
+        let bytes = self.evaluate_scalar(value)?.to_bytes(dtype);
+        let place = match target.place {
+            Some(place) => place,
+            None if target.bytes.is_none() => Place::OptimizedOut,
+            None => Place::Value(Vec::new()),
+        };
+        match place {
+            Place::Memory(addr) => self.write_memory(addr, &bytes),
+            // Registers of callers are saved on the stack somewhere; only the innermost frame's
+            // are live
+            Place::Register(register) if self.selected_frame == 0 => {
+                let in_register = format!(", which is in register ${}", registers::dwarf_name(register));
+                let name = registers::dwarf_register(register).ok_or_else(|| cannot_assign(in_register))?;
+                let mut regs = ptrace::getregs(inferior.tid())
+                    .map_err(|err| DebuggerError::Ptrace("read registers", err))?;
+                // Only the low bytes of the register hold the variable
+                let mut value = registers::get(&regs, name).unwrap_or(0).to_le_bytes();
+                value[..bytes.len()].copy_from_slice(&bytes);
+                registers::set(&mut regs, name, u64::from_le_bytes(value));
+                ptrace::setregs(inferior.tid(), regs)
+                    .map_err(|err| DebuggerError::Ptrace("write registers", err))
+            }
+            Place::Register(register) => Err(cannot_assign(format!(
+                ", which is in register ${} of an outer frame",
+                registers::dwarf_name(register)
+            ))),
+            Place::Value(_) => Err(cannot_assign(", which isn't an lvalue.".to_string())),
+            Place::OptimizedOut => Err(cannot_assign(", which has been optimized out.".to_string())),
+        }
+    }
+
+    fn set_memory(&mut self, addr: &str, value: &str) -> Result<(), DebuggerError> {
+        let addr = self.eval_address(addr)?;
+        let mut bytes = Vec::new();
+        for token in value.split_whitespace() {
+            let byte = if token.to_lowercase().starts_with("0x") {
+                u8::from_str_radix(&token[2..], 16)
+            } else {
+                token.parse::<u8>()
+            };
+            let byte = byte.map_err(|_| {
+                DebuggerError::InvalidArgument(format!("Invalid byte \"{}\"", token))
+            })?;
+            bytes.push(byte);
+        }
+        if bytes.is_empty() {
+            return Err(DebuggerError::Usage("set *<address> = <byte> [<byte>...]"));
+        }
+        self.write_memory(addr, &bytes)
+    }
+
+    /// Writes to the inferior's memory, then rereads watched values so that the write itself
+    /// isn't reported as a change.
+    fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_mut().ok_or(DebuggerError::NotRunning)?;
+        inferior
+            .write_memory(addr, bytes)
+            .map_err(|err| DebuggerError::Memory(addr, err))?;
+        for index in 0..self.breakpoint.len() {
+            self.refresh_watch_value(index);
+        }
+        Ok(())
+    }
+
+    fn set_register(&mut self, name: &str, value: &str) -> Result<(), DebuggerError> {
+        let inferior = self.inferior.as_ref().ok_or(DebuggerError::NotRunning)?;
+        let value = match self.evaluate_scalar(value)? {
+            Scalar::Int(value) => value as u64,
+            Scalar::Float(value) => value as i64 as u64,
+        };
+        let mut regs = ptrace::getregs(inferior.tid())
+            .map_err(|err| DebuggerError::Ptrace("read registers", err))?;
+        if !registers::set(&mut regs, name, value) {
+            return Err(DebuggerError::InvalidArgument(format!("Invalid register \"${}\"", name)));
+        }
+        ptrace::setregs(inferior.tid(), regs)
+            .map_err(|err| DebuggerError::Ptrace("write registers", err))?;
+        // Moving rip or rsp moves us to a different place in the program
+        self.selected_frame = 0;
+        self.list_position = None;
+        Ok(())
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
@@ -186,7 +2280,9 @@ impl Debugger {
                     return DebuggerCommand::Quit;
                 }
                 Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    // Nothing more can be read, so treat it like ctrl+d
+                    println!("{}", DebuggerError::Readline(err));
+                    return DebuggerCommand::Quit;
                 }
                 Ok(line) => {
                     if line.trim().len() == 0 {
@@ -200,10 +2296,9 @@ impl Debugger {
                         );
                     }
                     let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    match DebuggerCommand::from_tokens(&tokens) {
+                        Ok(cmd) => return cmd,
+                        Err(err) => println!("{}", err),
                     }
                 }
             }
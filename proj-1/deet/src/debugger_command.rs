@@ -1,15 +1,54 @@
+use crate::debugger_error::DebuggerError;
 
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,
+    Attach(String),
+    Detach,
+    Gcore(Option<String>),
     Backtrace,
     Break(String),
+    Print(String),
+    Ptype(String),
+    Examine(Option<String>, String),
+    Disassemble(String),
+    Set(String),
+    List(Option<String>),
+    Step,
+    Next,
+    StepInstruction(Option<String>),
+    NextInstruction(Option<String>),
+    Finish,
+    Watch(String),
+    Up(Option<String>),
+    Down(Option<String>),
+    Frame(Option<String>),
+    InfoBreakpoints,
+    InfoLocals,
+    InfoRegisters(Vec<String>),
+    InfoArgs,
+    InfoSignals(Option<String>),
+    InfoInferiors,
+    Inferior(Option<String>),
+    InfoThreads,
+    Thread(Option<String>),
+    InfoSharedLibrary,
+    Handle(Vec<String>),
+    Signal(String),
+    Delete(Vec<String>),
+    Ignore(Vec<String>),
+    Enable(Vec<String>),
+    Disable(Vec<String>),
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
+    pub fn from_tokens(tokens: &Vec<&str>) -> Result<DebuggerCommand, DebuggerError> {
+        let name = match tokens.first() {
+            Some(name) => *name,
+            None => return Err(DebuggerError::UnknownCommand(String::new())),
+        };
+        let command = match name {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
                 let args = tokens[1..].to_vec();
@@ -18,12 +57,74 @@ impl DebuggerCommand {
                 ))
             }
             "c" | "continue" | "cont" => Some(DebuggerCommand::Continue),
+            "attach" => Some(DebuggerCommand::Attach(tokens[1..].join(" "))),
+            "detach" => Some(DebuggerCommand::Detach),
             "bt"|"back"|"backtrace" => Some(DebuggerCommand::Backtrace),
             "break"|"b" => {
-                Some(DebuggerCommand::Break(tokens[1].to_string()))
+                Some(DebuggerCommand::Break(tokens[1..].join(" ")))
             },
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction(tokens.get(1).map(|s| s.to_string()))),
+            "ni" | "nexti" => Some(DebuggerCommand::NextInstruction(tokens.get(1).map(|s| s.to_string()))),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "watch" => Some(DebuggerCommand::Watch(tokens[1..].join(" "))),
+            "up" => Some(DebuggerCommand::Up(tokens.get(1).map(|s| s.to_string()))),
+            "down" => Some(DebuggerCommand::Down(tokens.get(1).map(|s| s.to_string()))),
+            "f" | "frame" => Some(DebuggerCommand::Frame(tokens.get(1).map(|s| s.to_string()))),
+            "i" | "info" => match tokens.get(1) {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::InfoBreakpoints)
+                }
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"r") | Some(&"reg") | Some(&"registers") => Some(
+                    DebuggerCommand::InfoRegisters(tokens[2..].iter().map(|s| s.to_string()).collect()),
+                ),
+                Some(&"args") => Some(DebuggerCommand::InfoArgs),
+                Some(&"inferiors") => Some(DebuggerCommand::InfoInferiors),
+                Some(&"threads") => Some(DebuggerCommand::InfoThreads),
+                Some(&"sharedlibrary") | Some(&"dll") => Some(DebuggerCommand::InfoSharedLibrary),
+                Some(&"signals") | Some(&"handle") => {
+                    Some(DebuggerCommand::InfoSignals(tokens.get(2).map(|s| s.to_string())))
+                }
+                Some(subcommand) => {
+                    return Err(DebuggerError::UnknownCommand(format!("info {}", subcommand)))
+                }
+                None => return Err(DebuggerError::Usage("info breakpoints|locals|args|registers|signals|inferiors|threads|sharedlibrary")),
+            },
+            "d" | "delete" => Some(DebuggerCommand::Delete(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "ignore" => Some(DebuggerCommand::Ignore(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "enable" => Some(DebuggerCommand::Enable(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "disable" => Some(DebuggerCommand::Disable(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "handle" => Some(DebuggerCommand::Handle(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "thread" => Some(DebuggerCommand::Thread(tokens.get(1).map(|s| s.to_string()))),
+            "inferior" => Some(DebuggerCommand::Inferior(tokens.get(1).map(|s| s.to_string()))),
+            "gcore" => Some(DebuggerCommand::Gcore(tokens.get(1).map(|s| s.to_string()))),
+            "signal" => Some(DebuggerCommand::Signal(tokens[1..].join(" "))),
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "ptype" => Some(DebuggerCommand::Ptype(tokens[1..].join(" "))),
+            "set" => Some(DebuggerCommand::Set(tokens[1..].join(" "))),
+            "disas" | "disassemble" => Some(DebuggerCommand::Disassemble(tokens[1..].join(" "))),
+            cmd if cmd == "x" || cmd.starts_with("x/") => Some(DebuggerCommand::Examine(
+                cmd.get(2..).map(|spec| spec.to_string()),
+                tokens[1..].join(" "),
+            )),
+            "l" | "list" => Some(DebuggerCommand::List(
+                tokens.get(1).map(|_| tokens[1..].join(" ")),
+            )),
             // Default case:
             _ => None,
-        }
+        };
+        command.ok_or_else(|| DebuggerError::UnknownCommand(name.to_string()))
     }
 }
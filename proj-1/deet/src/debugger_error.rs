@@ -0,0 +1,93 @@
+use crate::dwarf_data::Error as DwarfError;
+use rustyline::error::ReadlineError;
+use std::fmt;
+
+/// Everything that can go wrong running a debugger command. None of these are fatal: the REPL
+/// prints the message and reads the next command, leaving the inferior as it was.
+#[derive(Debug)]
+pub enum DebuggerError {
+    /// The command needs an inferior, and there isn't one
+    NotRunning,
+    /// The command needs a stack to look at, and there isn't one
+    NoStack,
+    /// The command looks at the selected frame, and there isn't one
+    NoFrame,
+    /// "list" was given nothing to go on: no location, no running program and no main
+    NoSourceFile,
+    UnknownCommand(String),
+    /// A command was given missing or malformed arguments; holds its usage message
+    Usage(&'static str),
+    /// An argument that doesn't name anything: a bad breakpoint number, frame level, register,
+    /// source location and so on. Holds the complete message.
+    InvalidArgument(String),
+    /// A location names a function or source file that isn't in the program, though it may turn
+    /// up in a library loaded later. Holds the complete message.
+    NotDefined(String),
+    /// Reading or writing the inferior's memory failed at the given address
+    Memory(usize, nix::Error),
+    /// Some other ptrace or waitpid call failed; holds what we were trying to do
+    Ptrace(&'static str, nix::Error),
+    /// A source file couldn't be read
+    Source(String, std::io::Error),
+    /// The target couldn't be started
+    Spawn(String, std::io::Error),
+    /// A core file couldn't be loaded; holds its path and what was wrong with it
+    Core(String, String),
+    /// "gcore" couldn't write a core file
+    WriteCore(String, std::io::Error),
+    /// The target's debugging symbols couldn't be loaded
+    DebugInfo(String, DwarfError),
+    /// An expression couldn't be parsed or evaluated, or a variable couldn't be found
+    Evaluation(String),
+    /// Reading the next command failed
+    Readline(ReadlineError),
+}
+
+impl From<String> for DebuggerError {
+    fn from(message: String) -> Self {
+        DebuggerError::Evaluation(message)
+    }
+}
+
+impl From<&str> for DebuggerError {
+    fn from(message: &str) -> Self {
+        DebuggerError::Evaluation(message.to_string())
+    }
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebuggerError::NotRunning => write!(f, "The program is not being run."),
+            DebuggerError::NoStack => write!(f, "No stack."),
+            DebuggerError::NoFrame => write!(f, "No frame selected."),
+            DebuggerError::NoSourceFile => write!(f, "No source file to list."),
+            DebuggerError::UnknownCommand(command) => {
+                write!(f, "Undefined command: \"{}\".", command)
+            }
+            DebuggerError::Usage(usage) => write!(f, "Usage: {}", usage),
+            DebuggerError::InvalidArgument(message)
+            | DebuggerError::NotDefined(message)
+            | DebuggerError::Evaluation(message) => {
+                write!(f, "{}", message)
+            }
+            DebuggerError::Memory(addr, err) => {
+                write!(f, "Cannot access memory at address {:#x}: {}", addr, err)
+            }
+            DebuggerError::Ptrace(action, err) => write!(f, "Could not {}: {}", action, err),
+            DebuggerError::Source(file, err) => write!(f, "Could not read {}: {}", file, err),
+            DebuggerError::Spawn(target, err) => write!(f, "Could not start {}: {}", target, err),
+            DebuggerError::Core(path, err) => write!(f, "Could not load core file {}: {}", path, err),
+            DebuggerError::WriteCore(path, err) => {
+                write!(f, "Could not write core file {}: {}", path, err)
+            }
+            DebuggerError::DebugInfo(target, DwarfError::ErrorOpeningFile) => {
+                write!(f, "Could not open file {}", target)
+            }
+            DebuggerError::DebugInfo(target, DwarfError::DwarfFormatError(err)) => {
+                write!(f, "Could not load debugging symbols from {}: {:?}", target, err)
+            }
+            DebuggerError::Readline(err) => write!(f, "Could not read command: {}", err),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! x86-64 instruction decoding, for the `disassemble` and `x/i` commands.
+
+use iced_x86::{Code, Decoder, DecoderOptions, Formatter, GasFormatter, Instruction, Mnemonic};
+
+/// Longest possible x86 instruction, in bytes
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+pub struct DecodedInstruction {
+    pub addr: usize,
+    pub len: usize,
+    /// AT&T syntax, like gdb and objdump
+    pub text: String,
+    /// Where a direct jump or call goes
+    pub branch_target: Option<usize>,
+}
+
+/// Decodes the machine code in `bytes`, which was read from `addr`. Decodes at most `count`
+/// instructions if given; an instruction cut off by the end of `bytes` is left out.
+pub fn decode(bytes: &[u8], addr: usize, count: Option<usize>) -> Vec<DecodedInstruction> {
+    let mut decoder = Decoder::new(64, bytes, DecoderOptions::NONE);
+    decoder.set_ip(addr as u64);
+    let mut formatter = GasFormatter::new();
+    formatter.options_mut().set_uppercase_hex(false);
+    formatter.options_mut().set_branch_leading_zeroes(false);
+    formatter.options_mut().set_first_operand_char_index(7);
+    let mut instruction = Instruction::default();
+    let mut decoded = Vec::new();
+    while decoder.can_decode() && count.map_or(true, |count| decoded.len() < count) {
+        let offset = decoder.position();
+        decoder.decode_out(&mut instruction);
+        if instruction.code() == Code::INVALID {
+            if offset + MAX_INSTRUCTION_LEN > bytes.len() {
+                // Probably just cut off by the end of the buffer
+                break;
+            }
+            decoded.push(DecodedInstruction {
+                addr: addr + offset,
+                len: instruction.len(),
+                text: "(bad)".to_string(),
+                branch_target: None,
+            });
+            continue;
+        }
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        let target = instruction.near_branch_target();
+        decoded.push(DecodedInstruction {
+            addr: instruction.ip() as usize,
+            len: instruction.len(),
+            text,
+            branch_target: if target != 0 { Some(target as usize) } else { None },
+        });
+    }
+    decoded
+}
+
+/// If `bytes` (read from `addr`) starts with a call instruction, returns its length.
+pub fn call_length(bytes: &[u8], addr: usize) -> Option<usize> {
+    let mut decoder = Decoder::new(64, bytes, DecoderOptions::NONE);
+    decoder.set_ip(addr as u64);
+    let instruction = decoder.decode();
+    if instruction.mnemonic() == Mnemonic::Call {
+        Some(instruction.len())
+    } else {
+        None
+    }
+}
@@ -1,9 +1,19 @@
+use crate::examine;
 use crate::gimli_wrapper;
+use crate::unwind::CallFrameInfo;
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection, ObjectSegment, SymbolKind};
+use once_cell::unsync::OnceCell;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::{fmt, fs};
 
+/// Bounds how deeply nested types are followed, in case of cycles in malformed debug info
+pub const MAX_TYPE_DEPTH: usize = 16;
+
+/// Type ids are offsets into .debug_info, so each library gets this many ids to itself
+const LIBRARY_TYPE_IDS: usize = 1 << 40;
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
@@ -12,7 +22,97 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
+    types: HashMap<usize, Type>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    call_frame_info: CallFrameInfo,
+    /// Functions in the ELF symbol tables, for code without debugging information
+    symbols: Vec<Symbol>,
+    /// Link-time address of the entry point, for working out where a PIE was loaded
+    entry: usize,
+    /// Link-time address of the dynamic section, whose DT_DEBUG entry leads to the dynamic
+    /// linker's list of loaded libraries
+    dynamic: Option<usize>,
+    /// The dynamic linker (PT_INTERP) of a dynamically linked program
+    interpreter: Option<String>,
+    /// Link-time [start, end) of the loadable segments
+    extent: (usize, usize),
+    /// How far the object was moved when it was loaded: 0 for ordinary executables, the base
+    /// address for PIEs and shared libraries. Every address DwarfData takes or returns includes
+    /// it; the DWARF itself only knows link-time addresses.
+    load_bias: usize,
+    /// Shared libraries the program has loaded, as the dynamic linker last reported them
+    libraries: Vec<Library>,
+    /// Type ids of the next library to be loaded start here (see `Library::type_base`)
+    next_type_base: usize,
+}
+
+/// A function from an ELF symbol table.
+#[derive(Debug, Clone)]
+struct Symbol {
+    name: String,
+    address: usize,
+    size: usize,
+}
+
+/// A shared library the program has loaded. Its debugging information is only read the first
+/// time a lookup needs it.
+struct Library {
+    path: String,
+    load_bias: usize,
+    /// [start, end) of the library in memory
+    range: (usize, usize),
+    /// Added to the ids of the library's types, which are only unique within one file
+    type_base: usize,
+    /// Empty until the first lookup, then the debugging information if it could be loaded
+    data: OnceCell<Option<DwarfData>>,
+}
+
+impl Library {
+    fn new(path: &str, load_bias: usize, type_base: usize) -> Option<Library> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+        let object = object::File::parse(&mmap[..]).ok()?;
+        let (start, end) = segment_extent(&object)?;
+        Some(Library {
+            path: path.to_string(),
+            load_bias,
+            range: (start.wrapping_add(load_bias), end.wrapping_add(load_bias)),
+            type_base,
+            data: OnceCell::new(),
+        })
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        self.range.0 <= addr && addr < self.range.1
+    }
+
+    /// Returns the library's debugging information, reading it if this is the first time.
+    fn data(&self) -> Option<&DwarfData> {
+        self.data
+            .get_or_init(|| {
+                DwarfData::from_file(&self.path).ok().map(|mut data| {
+                    data.rebase_types(self.type_base);
+                    data.set_load_bias(self.load_bias);
+                    data
+                })
+            })
+            .as_ref()
+    }
+
+    /// Returns the debugging information if it has already been read.
+    fn loaded(&self) -> Option<&DwarfData> {
+        self.data.get()?.as_ref()
+    }
+}
+
+/// Returns the link-time [start, end) of an object's loadable segments.
+fn segment_extent(object: &object::File) -> Option<(usize, usize)> {
+    let start = object.segments().map(|segment| segment.address()).min()?;
+    let end = object
+        .segments()
+        .map(|segment| segment.address() + segment.size())
+        .max()?;
+    Some((start as usize, end as usize))
 }
 
 impl fmt::Debug for DwarfData {
@@ -38,17 +138,225 @@ impl DwarfData {
         } else {
             gimli::RunTimeEndian::Big
         };
+        let (files, types) = gimli_wrapper::load_file(&object, endian)?;
+        let symbols = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter_map(|(_, symbol)| match (symbol.kind(), symbol.name()) {
+                (SymbolKind::Text, Some(name)) if !symbol.is_undefined() && symbol.address() != 0 => {
+                    Some(Symbol {
+                        name: name.to_string(),
+                        address: symbol.address() as usize,
+                        size: symbol.size() as usize,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        let interpreter = object.section_by_name(".interp").and_then(|section| {
+            let data = section.data().ok()?;
+            let end = data.iter().position(|byte| *byte == 0).unwrap_or(data.len());
+            Some(String::from_utf8_lossy(&data[..end]).into_owned())
+        });
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
+            files,
+            types,
             addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            call_frame_info: CallFrameInfo::load(&object, endian),
+            symbols,
+            entry: object.entry() as usize,
+            dynamic: object.section_by_name(".dynamic").map(|section| section.address() as usize),
+            interpreter,
+            extent: segment_extent(&object).unwrap_or((0, 0)),
+            load_bias: 0,
+            libraries: Vec::new(),
+            // The program's own ids are below this
+            next_type_base: LIBRARY_TYPE_IDS,
         })
     }
 
-    #[allow(dead_code)]
+    /// Returns the link-time address of the entry point.
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Records where the object was loaded, moving every address it knows about accordingly.
+    pub fn set_load_bias(&mut self, load_bias: usize) {
+        let delta = load_bias.wrapping_sub(self.load_bias);
+        if delta == 0 {
+            return;
+        }
+        for file in &mut self.files {
+            for var in &mut file.global_variables {
+                var.location.relocate(delta);
+            }
+            for func in &mut file.functions {
+                func.relocate(delta);
+            }
+            for line in &mut file.lines {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+        for symbol in &mut self.symbols {
+            symbol.address = symbol.address.wrapping_add(delta);
+        }
+        self.load_bias = load_bias;
+        self.call_frame_info.set_load_bias(load_bias);
+    }
+
+    /// Returns the program's dynamic linker, if it is dynamically linked.
+    pub fn interpreter(&self) -> Option<&str> {
+        self.interpreter.as_deref()
+    }
+
+    /// Returns the address of the program's dynamic section in memory.
+    pub fn dynamic_address(&self) -> Option<usize> {
+        self.dynamic.map(|addr| addr.wrapping_add(self.load_bias))
+    }
+
+    /// Replaces the list of shared libraries with (path, load bias) pairs from the dynamic
+    /// linker. Libraries that were already known keep whatever has been read about them;
+    /// libraries whose files can't be read are left out.
+    pub fn set_libraries(&mut self, libraries: &[(String, usize)]) {
+        let mut known: Vec<Library> = self.libraries.drain(..).collect();
+        for (path, load_bias) in libraries {
+            match known
+                .iter()
+                .position(|library| &library.path == path && library.load_bias == *load_bias)
+            {
+                Some(index) => self.libraries.push(known.remove(index)),
+                None => {
+                    if let Some(library) = Library::new(path, *load_bias, self.next_type_base) {
+                        self.next_type_base += LIBRARY_TYPE_IDS;
+                        self.libraries.push(library);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the (path, load bias) of each shared library.
+    pub fn libraries(&self) -> Vec<(String, usize)> {
+        self.libraries
+            .iter()
+            .map(|library| (library.path.clone(), library.load_bias))
+            .collect()
+    }
+
+    /// Describes each shared library for "info sharedlibrary": its path, where it is in memory,
+    /// and whether its symbols have been read (Some(true) if they include debugging information).
+    pub fn describe_libraries(&self) -> Vec<(&str, usize, usize, Option<bool>)> {
+        self.libraries
+            .iter()
+            .map(|library| {
+                let symbols = library.loaded().map(|data| !data.files.is_empty());
+                (library.path.as_str(), library.range.0, library.range.1, symbols)
+            })
+            .collect()
+    }
+
+    /// Returns the address of the dynamic linker's _dl_debug_state, which it calls each time it
+    /// has finished loading or unloading libraries.
+    pub fn library_event_address(&self) -> Option<usize> {
+        let interpreter = self.interpreter.as_ref()?;
+        let library = self.libraries.iter().find(|library| &library.path == interpreter)?;
+        library
+            .data()?
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == "_dl_debug_state")
+            .map(|symbol| symbol.address)
+    }
+
+    /// Returns the debugging information of the object (the program or one of its libraries)
+    /// that `addr` falls in.
+    fn object_for_addr(&self, addr: usize) -> &DwarfData {
+        let in_program = self.extent.0.wrapping_add(self.load_bias) <= addr
+            && addr < self.extent.1.wrapping_add(self.load_bias);
+        if in_program {
+            return self;
+        }
+        self.libraries
+            .iter()
+            .find(|library| library.contains(addr))
+            .and_then(Library::data)
+            .unwrap_or(self)
+    }
+
+    /// The program followed by each of its libraries, for lookups by name. Libraries are only
+    /// read if the lookup gets that far.
+    fn objects(&self) -> impl Iterator<Item = &DwarfData> {
+        std::iter::once(self).chain(self.libraries.iter().filter_map(Library::data))
+    }
+
+    /// Adds `base` to the ids of all of the object's types, so that they don't collide with the
+    /// program's.
+    fn rebase_types(&mut self, base: usize) {
+        self.types = self
+            .types
+            .drain()
+            .map(|(id, mut dtype)| {
+                dtype.rebase(base);
+                (id + base, dtype)
+            })
+            .collect();
+        for file in &mut self.files {
+            for var in &mut file.global_variables {
+                var.entity_type.rebase(base);
+            }
+            for func in &mut file.functions {
+                if let Some(return_type) = func.return_type.as_mut() {
+                    return_type.rebase(base);
+                }
+                for var in &mut func.variables {
+                    var.entity_type.rebase(base);
+                }
+            }
+        }
+    }
+
+    pub fn call_frame_info(&self, addr: usize) -> &CallFrameInfo {
+        &self.object_for_addr(addr).call_frame_info
+    }
+
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+        self.find_files(file).into_iter().next()
+    }
+
+    /// Returns every compilation unit whose file matches `file`, which may be the name the
+    /// compiler was given, a full path, or a trailing part of the path such as the basename.
+    /// Libraries are only searched if the program has no such file.
+    pub fn find_files(&self, file: &str) -> Vec<&File> {
+        self.objects()
+            .map(|object| {
+                object
+                    .files
+                    .iter()
+                    .filter(|f| {
+                        f.name == file
+                            || f.name.ends_with(&format!("/{}", file))
+                            || file.ends_with(&format!("/{}", f.name))
+                    })
+                    .collect::<Vec<&File>>()
+            })
+            .find(|files| !files.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Returns the path of a source file as recorded in the line table (which includes the
+    /// compilation directory), given a name as the user would type it.
+    pub fn get_source_path(&self, file: &str) -> Option<String> {
+        let target_file = self.get_target_file(file)?;
+        target_file
+            .lines
+            .first()
+            .and_then(|line| self.get_line_from_addr(line.address))
+            .map(|line| line.file)
+            .or_else(|| Some(target_file.name.clone()))
     }
 
     #[allow(dead_code)]
@@ -57,41 +365,66 @@ impl DwarfData {
             Some(filename) => self.get_target_file(filename)?,
             None => self.files.get(0)?,
         };
+        // Lines without code get the next line that has some
         Some(
             target_file
                 .lines
                 .iter()
-                .find(|line| line.number >= line_number)?
+                .filter(|line| line.number >= line_number)
+                .min_by_key(|line| (line.number, line.address))?
                 .address,
         )
     }
 
+    /// Returns the address of a function, looking in the debugging information and then the
+    /// symbol table of the program and then of each library in turn.
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
+        if let Some(filename) = file {
+            return Some(
                 self.get_target_file(filename)?
                     .functions
                     .iter()
                     .find(|func| func.name == func_name)?
                     .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
-            }
+            );
+        }
+        self.objects().find_map(|object| {
+            object
+                .files
+                .iter()
+                .flat_map(|file| file.functions.iter())
+                .find(|func| func.name == func_name && func.text_length > 0)
+                .map(|func| func.address)
+                .or_else(|| {
+                    object
+                        .symbols
+                        .iter()
+                        .find(|symbol| symbol.name == func_name)
+                        .map(|symbol| symbol.address)
+                })
+        })
+    }
+
+    /// Returns every function named `func_name` (in `file`, if given) along with the file that
+    /// defines it. Static functions may share a name across files.
+    pub fn get_functions_named(&self, file: Option<&str>, func_name: &str) -> Vec<(&File, &Function)> {
+        match file {
+            Some(filename) => functions_named(self.find_files(filename), func_name),
+            None => self
+                .objects()
+                .map(|object| functions_named(object.files.iter().collect(), func_name))
+                .find(|functions| !functions.is_empty())
+                .unwrap_or_default(),
         }
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
+        let object = self.object_for_addr(curr_addr);
+        let location = object
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(curr_addr.wrapping_sub(object.load_bias) as u64)
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -100,15 +433,308 @@ impl DwarfData {
         })
     }
 
+    /// Names the function `curr_addr` is in, from the debugging information or, failing that,
+    /// the symbol table.
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
+        let object = self.object_for_addr(curr_addr);
+        let frame = object
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+            .find_frames(curr_addr.wrapping_sub(object.load_bias) as u64)
+            .ok()
+            .and_then(|mut frames| frames.next().ok())
+            .flatten();
+        match frame.and_then(|frame| frame.function) {
+            Some(function) => Some(function.raw_name().ok()?.to_string()),
+            None => object.get_symbol_from_addr(curr_addr).map(|(name, _)| name),
+        }
+    }
+
+    /// Finds the symbol table function that contains `curr_addr`, with the offset into it.
+    fn get_symbol_from_addr(&self, curr_addr: usize) -> Option<(String, usize)> {
+        self.symbols
+            .iter()
+            .find(|symbol| {
+                symbol.address <= curr_addr && curr_addr < symbol.address + symbol.size.max(1)
+            })
+            .map(|symbol| (symbol.name.clone(), curr_addr - symbol.address))
+    }
+
+    /// Returns the function whose text contains `curr_addr`.
+    pub fn get_function_for_addr(&self, curr_addr: usize) -> Option<&Function> {
+        let object = self.object_for_addr(curr_addr);
+        object.files.iter().flat_map(|file| file.functions.iter()).find(|func| {
+            func.address <= curr_addr && curr_addr < func.address + func.text_length
+        })
+    }
+
+    /// Names the function or global variable that `curr_addr` falls in, along with the offset of
+    /// `curr_addr` into it.
+    pub fn get_symbol_for_addr(&self, curr_addr: usize) -> Option<(String, usize)> {
+        if let Some(func) = self.get_function_for_addr(curr_addr) {
+            return Some((func.name.clone(), curr_addr - func.address));
+        }
+        let object = self.object_for_addr(curr_addr);
+        object
+            .files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find_map(|var| match var.location {
+                Location::Address(addr)
+                    if addr <= curr_addr && curr_addr < addr + var.entity_type.size.max(1) =>
+                {
+                    Some((var.name.clone(), curr_addr - addr))
+                }
+                _ => None,
+            })
+            .or_else(|| object.get_symbol_from_addr(curr_addr))
+    }
+
+    /// Returns true if `curr_addr` is the first instruction of a line in the line table.
+    pub fn is_line_start(&self, curr_addr: usize) -> bool {
+        self.object_for_addr(curr_addr)
+            .files
+            .iter()
+            .any(|file| file.lines.iter().any(|line| line.address == curr_addr))
+    }
+
+    /// Returns the address of the first line after the function's prologue, which is where
+    /// breakpoints on the function should go so that its frame (and its variables) are set up.
+    pub fn get_prologue_end(&self, func: &Function) -> usize {
+        let (low, high) = func.text_range();
+        self.object_for_addr(func.address)
+            .files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|addr| low < *addr && *addr < high)
+            .min()
+            .unwrap_or(func.address)
+    }
+
+    /// Looks up a variable by name as seen from `curr_addr`: locals and parameters of the
+    /// enclosing function shadow globals, and globals in the enclosing file shadow those in other
+    /// files, which shadow those in other objects.
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<&Variable> {
+        if let Some(func) = self.get_function_for_addr(curr_addr) {
+            if let Some(var) = func.variables.iter().rev().find(|var| var.name == name) {
+                return Some(var);
+            }
+        }
+        let object = self.object_for_addr(curr_addr);
+        let current_file = object.files.iter().find(|file| {
+            file.functions.iter().any(|func| {
+                func.address <= curr_addr && curr_addr < func.address + func.text_length
+            })
+        });
+        global_named(current_file.into_iter().chain(object.files.iter()), name)
+            .or_else(|| self.objects().find_map(|object| global_named(object.files.iter(), name)))
+    }
+
+    /// Looks up a type by id, in whichever object it came from.
+    pub fn get_type(&self, type_id: usize) -> Option<&Type> {
+        self.types.get(&type_id).or_else(|| {
+            self.libraries
+                .iter()
+                .filter_map(Library::loaded)
+                .find_map(|library| library.types.get(&type_id))
+        })
+    }
+
+    /// Looks up a type by name, e.g. "int", "struct point" or a typedef.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        self.objects()
+            .find_map(|object| object.types.values().find(|dtype| dtype.name == name))
+    }
+
+    /// Looks up an enumeration constant such as `RED`, returning its enum type and value.
+    pub fn get_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.objects().find_map(|object| {
+            object.types.values().find_map(|dtype| match &dtype.kind {
+                TypeKind::Enum(values) => values
+                    .iter()
+                    .find(|(enumerator, _)| enumerator == name)
+                    .map(|(_, value)| (dtype, *value)),
+                _ => None,
+            })
+        })
+    }
+
+    /// Follows typedefs (and const/volatile) of structs, unions and arrays to the type itself.
+    pub fn resolve_alias<'a>(&'a self, dtype: &'a Type) -> &'a Type {
+        let mut dtype = dtype;
+        for _ in 0..MAX_TYPE_DEPTH {
+            match dtype.kind {
+                TypeKind::Alias(target) => match self.get_type(target) {
+                    Some(target) => dtype = target,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        dtype
+    }
+
+    /// Renders a value of any type from its raw bytes, e.g. `{x = 1, y = {2, 3}}` for a struct.
+    pub fn format_value(&self, dtype: &Type, bytes: &[u8]) -> String {
+        self.format_nested_value(dtype, bytes, 0)
+    }
+
+    fn format_nested_value(&self, dtype: &Type, bytes: &[u8], depth: usize) -> String {
+        let dtype = self.resolve_alias(dtype);
+        if depth > MAX_TYPE_DEPTH {
+            return "{...}".to_string();
+        }
+        match &dtype.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = match (self.get_type(member.type_id), member.offset) {
+                            (Some(member_type), Some(offset)) if offset + member_type.size <= bytes.len() => self
+                                .format_nested_value(
+                                    member_type,
+                                    &bytes[offset..offset + member_type.size],
+                                    depth + 1,
+                                ),
+                            _ => "<unknown>".to_string(),
+                        };
+                        format!("{} = {}", member.name, value)
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array(element, dimensions) => match self.get_type(*element) {
+                Some(element) => self.format_array(element, dimensions, bytes, depth),
+                None => "<unknown>".to_string(),
+            },
+            _ => dtype.format_value(bytes),
+        }
+    }
+
+    /// Returns the offset and type of each scalar in a value of type `dtype`, looking inside
+    /// structs, unions and arrays. Members at unknown offsets are left out.
+    pub fn scalar_fields<'a>(&'a self, dtype: &'a Type) -> Vec<(usize, &'a Type)> {
+        let mut fields = Vec::new();
+        self.collect_scalar_fields(dtype, 0, 0, &mut fields);
+        fields
+    }
+
+    fn collect_scalar_fields<'a>(
+        &'a self,
+        dtype: &'a Type,
+        offset: usize,
+        depth: usize,
+        fields: &mut Vec<(usize, &'a Type)>,
+    ) {
+        let dtype = self.resolve_alias(dtype);
+        if depth > MAX_TYPE_DEPTH {
+            return;
+        }
+        match &dtype.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                for member in members {
+                    if let (Some(member_type), Some(member_offset)) =
+                        (self.get_type(member.type_id), member.offset)
+                    {
+                        self.collect_scalar_fields(member_type, offset + member_offset, depth + 1, fields);
+                    }
+                }
+            }
+            TypeKind::Array(element, _) => {
+                if let Some(element) = self.get_type(*element).filter(|element| element.size > 0) {
+                    for index in 0..dtype.size / element.size {
+                        let start = offset + index * element.size;
+                        self.collect_scalar_fields(element, start, depth + 1, fields);
+                    }
+                }
+            }
+            _ => fields.push((offset, dtype)),
+        }
+    }
+
+    fn format_array(
+        &self,
+        element: &Type,
+        dimensions: &[Option<usize>],
+        bytes: &[u8],
+        depth: usize,
+    ) -> String {
+        const MAX_ELEMENTS: usize = 200;
+        let count = dimensions.first().cloned().unwrap_or(None).unwrap_or(0);
+        let stride = element.size
+            * dimensions
+                .get(1..)
+                .unwrap_or(&[])
+                .iter()
+                .map(|dimension| dimension.unwrap_or(0))
+                .product::<usize>();
+        let is_char = match self.resolve_alias(element).kind {
+            TypeKind::Base(Encoding::SignedChar) | TypeKind::Base(Encoding::UnsignedChar) => true,
+            _ => false,
+        };
+        if dimensions.len() == 1 && is_char {
+            // Show char arrays as strings, up to the terminating NUL
+            let bytes = &bytes[..count.min(bytes.len())];
+            let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+            return examine::quote_string(&bytes[..end]);
+        }
+        let mut items: Vec<String> = Vec::new();
+        for index in 0..count.min(MAX_ELEMENTS) {
+            let start = index * stride;
+            if stride == 0 || start + stride > bytes.len() {
+                break;
+            }
+            let item = &bytes[start..start + stride];
+            items.push(if dimensions.len() > 1 {
+                self.format_array(element, &dimensions[1..], item, depth + 1)
+            } else {
+                self.format_nested_value(element, item, depth + 1)
+            });
+        }
+        if count > MAX_ELEMENTS {
+            items.push("...".to_string());
+        }
+        format!("{{{}}}", items.join(", "))
+    }
+
+    /// Describes a type the way C would declare it, for "ptype". Structs, unions and enums are
+    /// shown with their members, and a typedef is shown as the type it names.
+    pub fn describe_type(&self, dtype: &Type) -> String {
+        let dtype = match dtype.kind {
+            TypeKind::Alias(target) => self.get_type(target).unwrap_or(dtype),
+            _ => dtype,
+        };
+        match &dtype.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                let mut text = format!("{} {{\n", dtype.name.trim_end_matches(" {...}"));
+                for member in members {
+                    let member_type = self
+                        .get_type(member.type_id)
+                        .map_or("<unknown>", |member_type| member_type.name.as_str());
+                    text.push_str(&format!("    {};\n", declaration(member_type, &member.name)));
+                }
+                text.push('}');
+                text
+            }
+            TypeKind::Enum(values) => {
+                let mut next = 0;
+                let names: Vec<String> = values
+                    .iter()
+                    .map(|(name, value)| {
+                        let shown = if *value == next {
+                            name.clone()
+                        } else {
+                            format!("{} = {}", name, value)
+                        };
+                        next = value + 1;
+                        shown
+                    })
+                    .collect();
+                format!("{} {{{}}}", dtype.name.trim_end_matches(" {...}"), names.join(", "))
+            }
+            _ => dtype.name.clone(),
+        }
     }
 
     #[allow(dead_code)]
@@ -148,32 +774,234 @@ impl DwarfData {
     }
 }
 
+/// Returns the functions named `name` in `files`, skipping declarations, which have no code.
+fn functions_named<'a>(files: Vec<&'a File>, name: &str) -> Vec<(&'a File, &'a Function)> {
+    files
+        .into_iter()
+        .flat_map(|file| file.functions.iter().map(move |func| (file, func)))
+        .filter(|(_, func)| func.name == name && func.text_length > 0)
+        .collect()
+}
+
+/// Returns the first global variable named `name` in `files`.
+fn global_named<'a>(files: impl Iterator<Item = &'a File>, name: &str) -> Option<&'a Variable> {
+    files
+        .flat_map(|file| file.global_variables.iter())
+        .find(|var| var.name == name)
+}
+
+/// Declares `name` as having the type named `type_name`, e.g. "int x[4]" or "void (*callback)()".
+fn declaration(type_name: &str, name: &str) -> String {
+    if let Some(pos) = type_name.find("(*)") {
+        return format!("{}(*{}){}", &type_name[..pos], name, &type_name[pos + 3..]);
+    }
+    if let Some(pos) = type_name.find(" [") {
+        return format!("{} {}{}", &type_name[..pos], name, &type_name[pos + 1..]);
+    }
+    if type_name.ends_with('*') {
+        return format!("{}{}", type_name, name);
+    }
+    format!("{} {}", type_name, name)
+}
+
+/// How the bits of a base type are interpreted (DW_AT_encoding).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+}
+
+/// A field of a struct or union.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Byte offset from the start of the struct, or None if the debugging information gives it as
+    /// an expression we can't evaluate
+    pub offset: Option<usize>,
+    pub type_id: usize,
+}
+
+/// What kind of type a `Type` is. Other types are referred to by id (their offset in
+/// .debug_info), which `DwarfData::get_type` looks up, since types may refer to themselves.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base(Encoding),
+    /// The pointed-to type, or None for void *
+    Pointer(Option<usize>),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// Element type, and the length of each dimension (None when unknown, as in `int x[]`)
+    Array(usize, Vec<Option<usize>>),
+    Enum(Vec<(String, i64)>),
+    /// A typedef, const or volatile version of a struct, union or array type. Those of scalar
+    /// types take on the kind of the type they name instead.
+    Alias(usize),
+    Function,
+    Void,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Void
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
+    /// The id other types refer to this one by (see `TypeKind`)
+    pub id: usize,
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
         Type {
+            id: 0,
             name: name,
             size: size,
+            kind: kind,
+        }
+    }
+
+    /// Moves the type, and the types it refers to, up by `base` ids (see `LIBRARY_TYPE_IDS`).
+    fn rebase(&mut self, base: usize) {
+        self.id += base;
+        match &mut self.kind {
+            TypeKind::Pointer(Some(target)) | TypeKind::Alias(target) | TypeKind::Array(target, _) => {
+                *target += base
+            }
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                for member in members {
+                    member.type_id += base;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        match self.kind {
+            TypeKind::Base(Encoding::Float) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true for types whose values should be sign-extended.
+    pub fn is_signed(&self) -> bool {
+        match self.kind {
+            TypeKind::Base(Encoding::Signed) | TypeKind::Base(Encoding::SignedChar) => true,
+            TypeKind::Enum(ref values) => values.iter().any(|(_, value)| *value < 0),
+            _ => false,
+        }
+    }
+
+    /// Returns true for types that fit in a register: numbers, pointers and enums.
+    pub fn is_scalar(&self) -> bool {
+        match self.kind {
+            TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_) => self.size <= 8,
+            _ => false,
+        }
+    }
+
+    /// Renders a value of this type from its raw (little-endian) bytes in the inferior. Only
+    /// understands scalars; `DwarfData::format_value` handles the rest.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        let mut buf = [0u8; 8];
+        let len = bytes.len().min(8);
+        buf[..len].copy_from_slice(&bytes[..len]);
+        let raw = u64::from_le_bytes(buf);
+        // Sign-extend from the type's width
+        let shift = 64 - 8 * len.max(1) as u32;
+        let signed = ((raw << shift) as i64) >> shift;
+
+        if bytes.len() > 8 || !self.is_scalar() {
+            // long double and friends: not worth decoding, so show the raw bytes
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            return format!("0x{}", hex.join(""));
+        }
+        match &self.kind {
+            TypeKind::Pointer(_) => format!("{:#x}", raw),
+            TypeKind::Base(Encoding::Boolean) => (raw != 0).to_string(),
+            TypeKind::Base(Encoding::Float) if len == 4 => f32::from_bits(raw as u32).to_string(),
+            TypeKind::Base(Encoding::Float) if len == 8 => f64::from_bits(raw).to_string(),
+            TypeKind::Base(Encoding::SignedChar) => format!("{} {}", signed, examine::quote_char(raw as u8)),
+            TypeKind::Base(Encoding::UnsignedChar) => format!("{} {}", raw, examine::quote_char(raw as u8)),
+            TypeKind::Enum(values) => {
+                let value = if self.is_signed() { signed } else { raw as i64 };
+                match values.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            _ if self.is_signed() => signed.to_string(),
+            _ => raw.to_string(),
         }
     }
 }
 
 #[derive(Clone)]
 pub enum Location {
+    /// A fixed address, as for globals
     Address(usize),
-    FramePointerOffset(isize),
+    /// Any other DWARF location expression, evaluated against the frame by `location::locate`
+    Expression(Expression),
+    /// A location list: different expressions for different [start, end) ranges of pc values,
+    /// as optimized code moves a variable between registers and the stack
+    List(Vec<(usize, usize, Expression)>),
+    /// The compiler folded the variable into a constant (DW_AT_const_value)
+    Value(Vec<u8>),
+    /// The variable has no location at all
+    OptimizedOut,
+}
+
+impl Location {
+    /// Moves the addresses in the location by `delta` (see `DwarfData::set_load_bias`).
+    fn relocate(&mut self, delta: usize) {
+        match self {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::Expression(expr) => expr.relocate(delta),
+            Location::List(ranges) => {
+                for (start, end, expr) in ranges {
+                    *start = start.wrapping_add(delta);
+                    *end = end.wrapping_add(delta);
+                    expr.relocate(delta);
+                }
+            }
+            Location::Value(_) | Location::OptimizedOut => {}
+        }
+    }
+}
+
+/// The bytecode of a DWARF expression, along with the encoding of the unit it came from.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub bytes: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// The load bias of the object the expression came from, to be added to the link-time
+    /// addresses (DW_OP_addr) in it
+    pub bias: usize,
+}
+
+impl Expression {
+    fn relocate(&mut self, delta: usize) {
+        self.bias = self.bias.wrapping_add(delta);
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
-            Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expr) => write!(f, "Expression({:02x?})", expr.bytes),
+            Location::List(ranges) => write!(f, "List({} ranges)", ranges.len()),
+            Location::Value(bytes) => write!(f, "Value({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -191,6 +1019,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -200,6 +1029,26 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub return_type: Option<Type>,
+    /// DW_AT_frame_base, which DW_OP_fbreg locations are relative to
+    pub frame_base: Option<Expression>,
+}
+
+impl Function {
+    /// Returns the [low, high) range of addresses occupied by the function's code.
+    pub fn text_range(&self) -> (usize, usize) {
+        (self.address, self.address + self.text_length)
+    }
+
+    fn relocate(&mut self, delta: usize) {
+        self.address = self.address.wrapping_add(delta);
+        for var in &mut self.variables {
+            var.location.relocate(delta);
+        }
+        if let Some(frame_base) = self.frame_base.as_mut() {
+            frame_base.relocate(delta);
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -223,4 +1072,38 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples;
 
+    #[test]
+    fn library_types_are_kept_apart() {
+        let path = samples::path("function_calls");
+        let mut data = DwarfData::from_file(&path).unwrap();
+        // Have the program use the id the library's int will have in its own file
+        let int_id = data.get_type_by_name("int").unwrap().id;
+        let decoy = Type {
+            id: int_id,
+            ..Type::new("decoy".to_string(), 4, TypeKind::Base(Encoding::Signed))
+        };
+        data.types.insert(int_id, decoy);
+
+        data.set_libraries(&[(path.clone(), 0x7f00_0000_0000)]);
+        let library = data.libraries[0].data().unwrap();
+        assert!(library.types.keys().all(|id| !data.types.contains_key(id)));
+        let global = global_named(library.files.iter(), "global").unwrap();
+        assert_eq!(data.get_type(global.entity_type.id).unwrap().name, "int");
+    }
+
+    #[test]
+    fn arrays_without_dimensions() {
+        let data = DwarfData::from_file(&samples::path("hello")).unwrap();
+        let int = data.get_type_by_name("int").unwrap().clone();
+        // An array DIE with no DW_TAG_subrange_type children
+        let array = Type::new("int []".to_string(), 0, TypeKind::Array(int.id, Vec::new()));
+        assert_eq!(data.format_value(&array, &[1, 0, 0, 0]), "{}");
+        let matrix = Type::new("int [2][]".to_string(), 0, TypeKind::Array(int.id, vec![Some(2), None]));
+        assert_eq!(data.format_value(&matrix, &[1, 0, 0, 0]), "{}");
+    }
+}
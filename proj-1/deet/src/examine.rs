@@ -0,0 +1,183 @@
+//! Formatting for the `x` (examine memory) command.
+
+/// An `x/<count><letter><size>` format. The letter and size stick around for later `x` commands
+/// that don't give them, like in gdb.
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    pub count: usize,
+    pub letter: char,
+    pub size: usize,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format {
+            count: 1,
+            letter: 'x',
+            size: 4,
+        }
+    }
+}
+
+impl Format {
+    /// Parses the part after the slash, e.g. "4xg" or "s". Missing pieces come from `previous`,
+    /// except the count, which defaults to 1.
+    pub fn parse(spec: &str, previous: &Format) -> Result<Format, String> {
+        let digits: String = spec.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let mut format = Format {
+            count: 1,
+            ..*previous
+        };
+        if !digits.is_empty() {
+            format.count = digits
+                .parse()
+                .map_err(|_| format!("Invalid count \"{}\"", digits))?;
+        }
+        let mut size_given = false;
+        for c in spec[digits.len()..].chars() {
+            match c {
+                'b' => format.size = 1,
+                'h' => format.size = 2,
+                'w' => format.size = 4,
+                'g' => format.size = 8,
+                'x' | 'd' | 'u' | 'o' | 't' | 'a' | 'c' | 's' | 'i' => {
+                    format.letter = c;
+                    continue;
+                }
+                _ => return Err(format!("Undefined output format \"{}\".", c)),
+            }
+            size_given = true;
+        }
+        // Characters are single bytes and addresses are whole words unless asked otherwise
+        if !size_given {
+            match format.letter {
+                'c' => format.size = 1,
+                'a' => format.size = 8,
+                _ => {}
+            }
+        }
+        Ok(format)
+    }
+
+    /// Number of units shown on each line of output.
+    pub fn units_per_line(&self) -> usize {
+        match (self.letter, self.size) {
+            ('x', 1) => 16,
+            ('c', _) => 8,
+            ('t', _) => 8 / self.size.max(1),
+            (_, 1) | (_, 2) => 8,
+            (_, 4) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Formats one unit of memory.
+    pub fn format_unit(&self, bytes: &[u8]) -> String {
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(buf);
+        let bits = 8 * bytes.len() as u32;
+        let shift = 64 - bits;
+        let signed = ((raw << shift) as i64) >> shift;
+        match self.letter {
+            'x' if self.size == 1 => format!("{:02x}", raw),
+            'x' => format!("0x{:0width$x}", raw, width = 2 * self.size),
+            'd' => signed.to_string(),
+            'u' => raw.to_string(),
+            'o' => format!("0{:o}", raw),
+            't' => format!("{:0width$b}", raw, width = bits as usize),
+            'a' => format!("{:#x}", raw),
+            'c' => format!("{} {}", signed, quote_char(raw as u8)),
+            _ => format!("{:#x}", raw),
+        }
+    }
+}
+
+/// Quotes a byte as a C character literal, e.g. 'A' or '\n'.
+pub fn quote_char(byte: u8) -> String {
+    format!("'{}'", escape(byte, '\''))
+}
+
+/// Quotes bytes as a C string literal.
+pub fn quote_string(bytes: &[u8]) -> String {
+    let escaped: String = bytes.iter().map(|byte| escape(*byte, '"')).collect();
+    format!("\"{}\"", escaped)
+}
+
+fn escape(byte: u8, quote: char) -> String {
+    match byte {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        0 => "\\0".to_string(),
+        b'\\' => "\\\\".to_string(),
+        byte if byte as char == quote => format!("\\{}", quote),
+        0x20..=0x7e => (byte as char).to_string(),
+        byte => format!("\\{:03o}", byte),
+    }
+}
+
+/// Renders bytes as printable ASCII for the gutter of a hex dump, with dots for everything else.
+pub fn ascii_gutter(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| match byte {
+            0x20..=0x7e => *byte as char,
+            _ => '.',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str, previous: &Format) -> (usize, char, usize) {
+        let format = Format::parse(spec, previous).unwrap();
+        (format.count, format.letter, format.size)
+    }
+
+    #[test]
+    fn parse_format() {
+        let default = Format::default();
+        assert_eq!(parse("", &default), (1, 'x', 4));
+        assert_eq!(parse("4xg", &default), (4, 'x', 8));
+        assert_eq!(parse("16b", &default), (16, 'x', 1));
+        assert_eq!(parse("gd", &default), (1, 'd', 8));
+        assert_eq!(parse("3c", &default), (3, 'c', 1));
+        assert_eq!(parse("a", &default), (1, 'a', 8));
+        assert_eq!(parse("ch", &default), (1, 'c', 2));
+        assert!(Format::parse("4q", &default).is_err());
+        assert!(Format::parse("99999999999999999999999", &default).is_err());
+    }
+
+    #[test]
+    fn parse_format_keeps_previous() {
+        let previous = Format::parse("8ub", &Format::default()).unwrap();
+        // The letter and size carry over, but the count doesn't
+        assert_eq!(parse("", &previous), (1, 'u', 1));
+        assert_eq!(parse("2h", &previous), (2, 'u', 2));
+        assert_eq!(parse("t", &previous), (1, 't', 1));
+    }
+
+    #[test]
+    fn format_units() {
+        let format = |spec: &str| Format::parse(spec, &Format::default()).unwrap();
+        assert_eq!(format("xb").format_unit(&[0xab]), "ab");
+        assert_eq!(format("xw").format_unit(&[0x78, 0x56, 0x34, 0x12]), "0x12345678");
+        assert_eq!(format("dh").format_unit(&[0xfe, 0xff]), "-2");
+        assert_eq!(format("uh").format_unit(&[0xfe, 0xff]), "65534");
+        assert_eq!(format("ob").format_unit(&[8]), "010");
+        assert_eq!(format("tb").format_unit(&[5]), "00000101");
+        assert_eq!(format("c").format_unit(b"\n"), "10 '\\n'");
+        assert_eq!(format("xb").units_per_line(), 16);
+        assert_eq!(format("xg").units_per_line(), 2);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_char(b'\''), "'\\''");
+        assert_eq!(quote_string(b"a\"b\0\x7f"), "\"a\\\"b\\0\\177\"");
+        assert_eq!(ascii_gutter(b"hi\n\xff"), "hi..");
+    }
+}
@@ -0,0 +1,1112 @@
+//! Parsing and evaluation of C expressions, for `print`, `x`, `set var`, breakpoint conditions
+//! and watchpoints. Supports arithmetic, comparisons and logic, `*ptr`, `&var`, `arr[i]`,
+//! `s.field`, `p->field`, casts to known types, and `$register` references.
+
+use crate::condition::Scalar;
+use crate::dwarf_data::{DwarfData, Encoding, Type, TypeKind};
+use crate::location::Place;
+use crate::registers;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// Binary operators with their precedence; higher binds tighter.
+const BINARY_OPS: [(&str, BinaryOp, u8); 18] = [
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Rem, 10),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("<", BinaryOp::Lt, 7),
+    ("<=", BinaryOp::Le, 7),
+    (">", BinaryOp::Gt, 7),
+    (">=", BinaryOp::Ge, 7),
+    ("==", BinaryOp::Eq, 6),
+    ("!=", BinaryOp::Ne, 6),
+    ("&", BinaryOp::BitAnd, 5),
+    ("^", BinaryOp::BitXor, 4),
+    ("|", BinaryOp::BitOr, 3),
+    ("&&", BinaryOp::And, 2),
+    ("||", BinaryOp::Or, 1),
+];
+
+/// Longest first, so that "<=" isn't read as "<" followed by "="
+const PUNCTUATION: [&str; 27] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "(", ")", "[", "]", ".", ",",
+];
+
+/// Words that start a type name in a cast
+const TYPE_KEYWORDS: [&str; 15] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+    "struct", "union", "enum", "const", "volatile",
+];
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Int(i64),
+    Float(f64),
+    Char(u8),
+    Variable(String),
+    Register(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Member(Box<Expr>, String),
+    /// The type is kept by name, e.g. "unsigned long" or "struct node *"
+    Cast(String, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Char(u8),
+    Ident(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '.') {
+                // Exponents may have a sign
+                if (chars[pos] == 'e' || chars[pos] == 'E')
+                    && !chars[start..pos].contains(&'x')
+                    && pos + 1 < chars.len()
+                    && (chars[pos + 1] == '-' || chars[pos + 1] == '+')
+                {
+                    pos += 1;
+                }
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            tokens.push(parse_number(&word)?);
+        } else if c == '\'' {
+            let (byte, len) = parse_char(&chars[pos..])?;
+            tokens.push(Token::Char(byte));
+            pos += len;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = pos;
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            tokens.push(if c == '$' {
+                Token::Register(word[1..].to_string())
+            } else {
+                Token::Ident(word)
+            });
+        } else {
+            let rest: String = chars[pos..].iter().take(2).collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(*punct))
+                .ok_or_else(|| format!("Invalid character '{}' in expression.", c))?;
+            tokens.push(Token::Punct(punct));
+            pos += punct.len();
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<Token, String> {
+    let invalid = || format!("Invalid number \"{}\".", word);
+    let lower = word.to_lowercase();
+    if lower.starts_with("0x") {
+        let digits = lower[2..].trim_end_matches(&['u', 'l'][..]);
+        return u64::from_str_radix(digits, 16)
+            .map(|value| Token::Int(value as i64))
+            .map_err(|_| invalid());
+    }
+    let digits = lower.trim_end_matches(&['u', 'l'][..]);
+    if let Ok(value) = digits.parse::<u64>() {
+        if digits.len() > 1 && digits.starts_with('0') {
+            return u64::from_str_radix(&digits[1..], 8)
+                .map(|value| Token::Int(value as i64))
+                .map_err(|_| invalid());
+        }
+        return Ok(Token::Int(value as i64));
+    }
+    lower
+        .trim_end_matches('f')
+        .parse::<f64>()
+        .map(Token::Float)
+        .map_err(|_| invalid())
+}
+
+/// Parses a character literal at the start of `chars`, returning it and its length.
+fn parse_char(chars: &[char]) -> Result<(u8, usize), String> {
+    let (byte, len) = match chars.get(1) {
+        Some('\\') => {
+            let byte = match chars.get(2) {
+                Some('n') => b'\n',
+                Some('t') => b'\t',
+                Some('r') => b'\r',
+                Some('0') => 0,
+                Some('\\') => b'\\',
+                Some('\'') => b'\'',
+                Some('"') => b'"',
+                _ => return Err("Invalid character constant.".to_string()),
+            };
+            (byte, 4)
+        }
+        Some(c) if c.is_ascii() => (*c as u8, 3),
+        _ => return Err("Unmatched single quote.".to_string()),
+    };
+    if chars.get(len - 1) != Some(&'\'') {
+        return Err("Unmatched single quote.".to_string());
+    }
+    Ok((byte, len))
+}
+
+/// Parses a C expression. The debug info is needed to tell casts to typedef'd types apart from
+/// parenthesized expressions.
+pub fn parse(text: &str, data: &DwarfData) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    if tokens.is_empty() {
+        return Err("Missing expression.".to_string());
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        data,
+    };
+    let expr = parser.parse_binary(1)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some(token) => Err(format!("A syntax error in expression, near {}.", describe(token))),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Int(value) => format!("`{}'", value),
+        Token::Float(value) => format!("`{}'", value),
+        Token::Char(byte) => format!("`{}'", *byte as char),
+        Token::Ident(name) => format!("`{}'", name),
+        Token::Register(name) => format!("`${}'", name),
+        Token::Punct(punct) => format!("`{}'", punct),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    data: &'a DwarfData,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_punct(&self, punct: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(p)) => *p == punct,
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.peek_punct(punct) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(token) => format!("A syntax error in expression, near {}.", describe(token)),
+                None => format!("Expected `{}' at the end of the expression.", punct),
+            })
+        }
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(punct)) => {
+                    BINARY_OPS.iter().find(|(text, _, _)| text == punct).cloned()
+                }
+                _ => None,
+            };
+            let (op, precedence) = match op {
+                Some((_, op, precedence)) if precedence >= min_precedence => (op, precedence),
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            // Binary operators are all left-associative
+            let rhs = self.parse_binary(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => Some(UnaryOp::Neg),
+            Some(Token::Punct("!")) => Some(UnaryOp::Not),
+            Some(Token::Punct("~")) => Some(UnaryOp::BitNot),
+            Some(Token::Punct("*")) => Some(UnaryOp::Deref),
+            Some(Token::Punct("&")) => Some(UnaryOp::AddressOf),
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.parse_unary();
+            }
+            Some(Token::Punct("(")) => {
+                if let Some(type_name) = self.parse_cast_type()? {
+                    let operand = self.parse_unary()?;
+                    return Ok(Expr::Cast(type_name, Box::new(operand)));
+                }
+                None
+            }
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            None => self.parse_postfix(),
+        }
+    }
+
+    /// If the parenthesis at the current position starts a cast, consumes the cast and returns
+    /// the type name in it.
+    fn parse_cast_type(&mut self) -> Result<Option<String>, String> {
+        let is_type = match self.tokens.get(self.pos + 1) {
+            Some(Token::Ident(word)) => {
+                TYPE_KEYWORDS.contains(&word.as_str()) || self.data.get_type_by_name(word).is_some()
+            }
+            _ => false,
+        };
+        if !is_type {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let mut words: Vec<String> = Vec::new();
+        let mut pointers = 0;
+        loop {
+            match self.peek() {
+                Some(Token::Ident(word)) if pointers == 0 => words.push(word.clone()),
+                Some(Token::Punct("*")) => pointers += 1,
+                Some(Token::Punct(")")) => break,
+                Some(token) => {
+                    return Err(format!("A syntax error in expression, near {}.", describe(token)))
+                }
+                None => return Err("Unterminated cast.".to_string()),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        let mut name = words.join(" ");
+        if pointers > 0 {
+            name.push(' ');
+            name.push_str(&"*".repeat(pointers));
+        }
+        Ok(Some(name))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.peek_punct("[") {
+                self.pos += 1;
+                let index = self.parse_binary(1)?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.peek_punct(".") || self.peek_punct("->") {
+                let arrow = self.peek_punct("->");
+                self.pos += 1;
+                let field = match self.peek() {
+                    Some(Token::Ident(field)) => field.clone(),
+                    _ => return Err("Expected a field name.".to_string()),
+                };
+                self.pos += 1;
+                if arrow {
+                    expr = Expr::Unary(UnaryOp::Deref, Box::new(expr));
+                }
+                expr = Expr::Member(Box::new(expr), field);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or("Expected an expression, but the input ended.")?;
+        self.pos += 1;
+        match token {
+            Token::Int(value) => Ok(Expr::Int(value)),
+            Token::Float(value) => Ok(Expr::Float(value)),
+            Token::Char(byte) => Ok(Expr::Char(byte)),
+            Token::Ident(name) => Ok(Expr::Variable(name)),
+            Token::Register(name) => Ok(Expr::Register(name)),
+            Token::Punct("(") => {
+                let expr = self.parse_binary(1)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            token => Err(format!("A syntax error in expression, near {}.", describe(&token))),
+        }
+    }
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub dtype: Type,
+    /// None if the value has been optimized out
+    pub bytes: Option<Vec<u8>>,
+    /// Where the value lives, if it's something that can be assigned to: `Place::Memory` or
+    /// `Place::Register`
+    pub place: Option<Place>,
+}
+
+impl Value {
+    pub fn bytes(&self) -> Result<&[u8], String> {
+        self.bytes
+            .as_deref()
+            .ok_or_else(|| "value has been optimized out".to_string())
+    }
+
+    pub fn address(&self) -> Option<usize> {
+        match self.place {
+            Some(Place::Memory(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+/// What evaluating an expression needs from the program being debugged.
+pub trait Context {
+    /// Looks up a variable visible from the selected frame.
+    fn variable(&self, name: &str) -> Result<Value, String>;
+    fn register(&self, name: &str) -> Result<u64, String>;
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, String>;
+}
+
+pub struct Evaluator<'a> {
+    data: &'a DwarfData,
+    context: &'a dyn Context,
+    /// Types made up during evaluation (pointers for `&`, the int results of arithmetic, ...),
+    /// with ids counting down from usize::MAX so they don't collide with the debug info's
+    synthetic: RefCell<Vec<Type>>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(data: &'a DwarfData, context: &'a dyn Context) -> Evaluator<'a> {
+        Evaluator {
+            data,
+            context,
+            synthetic: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(value) => {
+                let dtype = if *value as i32 as i64 == *value {
+                    self.int_type(4, true)
+                } else {
+                    self.int_type(8, true)
+                };
+                Ok(self.rvalue(&dtype, Scalar::Int(*value)))
+            }
+            Expr::Float(value) => {
+                let dtype = self.synthesize("double", 8, TypeKind::Base(Encoding::Float));
+                Ok(self.rvalue(&dtype, Scalar::Float(*value)))
+            }
+            Expr::Char(byte) => {
+                let dtype = self.synthesize("char", 1, TypeKind::Base(Encoding::SignedChar));
+                Ok(self.rvalue(&dtype, Scalar::Int(*byte as i64)))
+            }
+            Expr::Variable(name) => self.variable(name),
+            Expr::Register(name) => {
+                let value = self.context.register(name)?;
+                let dtype = if registers::is_pointer(name) {
+                    self.synthesize("void *", 8, TypeKind::Pointer(None))
+                } else {
+                    self.int_type(8, true)
+                };
+                Ok(self.rvalue(&dtype, Scalar::Int(value as i64)))
+            }
+            Expr::Unary(op, operand) => self.unary(*op, operand),
+            Expr::Binary(op, lhs, rhs) => self.binary(*op, lhs, rhs),
+            Expr::Index(base, index) => {
+                let base = self.evaluate(base)?;
+                let index = self.evaluate(index)?;
+                match self.scalar(&index)? {
+                    Scalar::Int(index) => self.index(&base, index),
+                    Scalar::Float(_) => Err("Array subscript is not an integer.".to_string()),
+                }
+            }
+            Expr::Member(object, field) => self.member(&self.evaluate(object)?, field),
+            Expr::Cast(type_name, operand) => {
+                let dtype = self.parse_type(type_name)?;
+                self.cast(&dtype, &self.evaluate(operand)?)
+            }
+        }
+    }
+
+    /// Interprets a value as a number. Arrays stand for the address of their first element.
+    pub fn scalar(&self, value: &Value) -> Result<Scalar, String> {
+        let dtype = self.resolve(&value.dtype);
+        match dtype.kind {
+            TypeKind::Base(_) | TypeKind::Enum(_) | TypeKind::Pointer(_) if dtype.is_scalar() => {
+                Ok(Scalar::from_bytes(&dtype, value.bytes()?))
+            }
+            TypeKind::Array(..) => value
+                .address()
+                .map(|addr| Scalar::Int(addr as i64))
+                .ok_or_else(|| "Attempt to take address of value not located in memory.".to_string()),
+            _ => Err(format!("Value of type {} is not a number.", dtype.name)),
+        }
+    }
+
+    /// Looks up a variable, or failing that an enumeration constant or a function, which stands
+    /// for its address.
+    fn variable(&self, name: &str) -> Result<Value, String> {
+        let err = match self.context.variable(name) {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if let Some((dtype, value)) = self.data.get_enumerator(name) {
+            return Ok(self.rvalue(dtype, Scalar::Int(value)));
+        }
+        let addr = match self.data.get_addr_for_function(None, name) {
+            Some(addr) => addr,
+            None => return Err(err),
+        };
+        let returns = self
+            .data
+            .get_functions_named(None, name)
+            .first()
+            .and_then(|(_, func)| func.return_type.as_ref())
+            .map_or("void".to_string(), |dtype| dtype.name.clone());
+        let dtype = self.synthesize(&format!("{} (*)()", returns), 8, TypeKind::Pointer(None));
+        Ok(self.rvalue(&dtype, Scalar::Int(addr as i64)))
+    }
+
+    fn unary(&self, op: UnaryOp, operand: &Expr) -> Result<Value, String> {
+        let value = self.evaluate(operand)?;
+        match op {
+            UnaryOp::Deref => {
+                let (addr, target) = self
+                    .pointer_target(&value)?
+                    .ok_or("Attempt to take contents of a non-pointer value.")?;
+                self.read_at(&target, addr)
+            }
+            UnaryOp::AddressOf => match value.place {
+                Some(Place::Memory(addr)) => {
+                    let pointer = self.pointer_to(&value.dtype);
+                    Ok(self.rvalue(&pointer, Scalar::Int(addr as i64)))
+                }
+                Some(Place::Register(register)) => Err(match operand {
+                    Expr::Variable(name) => format!(
+                        "Address requested for identifier \"{}\" which is in register ${}",
+                        name,
+                        registers::dwarf_name(register)
+                    ),
+                    _ => format!(
+                        "Address requested for value which is in register ${}",
+                        registers::dwarf_name(register)
+                    ),
+                }),
+                // Functions already evaluate to their address
+                _ if self.is_function(operand) => Ok(value),
+                _ => Err("Attempt to take address of value not located in memory.".to_string()),
+            },
+            UnaryOp::Not => {
+                let truth = self.scalar(&value)?.is_true();
+                Ok(self.rvalue(&self.int_type(4, true), Scalar::Int(!truth as i64)))
+            }
+            UnaryOp::Neg | UnaryOp::BitNot => {
+                let dtype = self.arithmetic_type(&value.dtype, &value.dtype);
+                match (op, self.scalar(&value)?) {
+                    (UnaryOp::Neg, Scalar::Float(value)) => Ok(self.rvalue(&dtype, Scalar::Float(-value))),
+                    (UnaryOp::Neg, Scalar::Int(value)) => {
+                        Ok(self.rvalue(&dtype, Scalar::Int(value.wrapping_neg())))
+                    }
+                    (_, Scalar::Int(value)) => Ok(self.rvalue(&dtype, Scalar::Int(!value))),
+                    (_, Scalar::Float(_)) => Err("Argument to complement operation not an integer.".to_string()),
+                }
+            }
+        }
+    }
+
+    fn binary(&self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<Value, String> {
+        let int = self.int_type(4, true);
+        // Logical operators only evaluate their right side if they have to
+        if op == BinaryOp::And || op == BinaryOp::Or {
+            let lhs = self.scalar(&self.evaluate(lhs)?)?.is_true();
+            let result = if lhs == (op == BinaryOp::Or) {
+                lhs
+            } else {
+                self.scalar(&self.evaluate(rhs)?)?.is_true()
+            };
+            return Ok(self.rvalue(&int, Scalar::Int(result as i64)));
+        }
+        let lhs = self.evaluate(lhs)?;
+        let rhs = self.evaluate(rhs)?;
+        if op == BinaryOp::Add || op == BinaryOp::Sub {
+            if let Some(result) = self.pointer_arithmetic(op, &lhs, &rhs)? {
+                return Ok(result);
+            }
+        }
+        let dtype = self.arithmetic_type(&lhs.dtype, &rhs.dtype);
+        let (left, right) = (self.scalar(&lhs)?, self.scalar(&rhs)?);
+
+        let ordering = match (left, right) {
+            (Scalar::Int(l), Scalar::Int(r)) if dtype.is_signed() => l.partial_cmp(&r),
+            (Scalar::Int(l), Scalar::Int(r)) => (l as u64).partial_cmp(&(r as u64)),
+            (l, r) => l.as_f64().partial_cmp(&r.as_f64()),
+        };
+        let comparison = match op {
+            BinaryOp::Eq => Some(ordering == Some(Ordering::Equal)),
+            // NaN compares unequal to everything
+            BinaryOp::Ne => Some(ordering != Some(Ordering::Equal)),
+            BinaryOp::Lt => Some(ordering == Some(Ordering::Less)),
+            BinaryOp::Le => Some(ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal)),
+            BinaryOp::Gt => Some(ordering == Some(Ordering::Greater)),
+            BinaryOp::Ge => {
+                Some(ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal))
+            }
+            _ => None,
+        };
+        if let Some(result) = comparison {
+            return Ok(self.rvalue(&int, Scalar::Int(result as i64)));
+        }
+
+        let result = match (left, right) {
+            (Scalar::Int(l), Scalar::Int(r)) => {
+                if (op == BinaryOp::Div || op == BinaryOp::Rem) && r == 0 {
+                    return Err("Division by zero".to_string());
+                }
+                let (ul, ur) = (l as u64, r as u64);
+                let signed = dtype.is_signed();
+                Scalar::Int(match op {
+                    BinaryOp::Mul => l.wrapping_mul(r),
+                    BinaryOp::Div if signed => l.wrapping_div(r),
+                    BinaryOp::Div => (ul / ur) as i64,
+                    BinaryOp::Rem if signed => l.wrapping_rem(r),
+                    BinaryOp::Rem => (ul % ur) as i64,
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Shl => l.wrapping_shl(r as u32),
+                    BinaryOp::Shr if signed => l.wrapping_shr(r as u32),
+                    BinaryOp::Shr => ul.wrapping_shr(r as u32) as i64,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::BitXor => l ^ r,
+                    _ => l | r,
+                })
+            }
+            (l, r) => {
+                let (l, r) = (l.as_f64(), r.as_f64());
+                Scalar::Float(match op {
+                    BinaryOp::Mul => l * r,
+                    BinaryOp::Div => l / r,
+                    BinaryOp::Add => l + r,
+                    BinaryOp::Sub => l - r,
+                    _ => return Err("Integer only operation.".to_string()),
+                })
+            }
+        };
+        Ok(self.rvalue(&dtype, result))
+    }
+
+    /// Handles `pointer + n`, `n + pointer`, `pointer - n` and `pointer - pointer`, which count
+    /// in elements rather than bytes. Returns None if neither side is a pointer.
+    fn pointer_arithmetic(&self, op: BinaryOp, lhs: &Value, rhs: &Value) -> Result<Option<Value>, String> {
+        let (left, right) = (self.pointer_target(lhs)?, self.pointer_target(rhs)?);
+        let stride = |target: &Type| target.size.max(1) as i64;
+        let (addr, target, offset) = match (left, right) {
+            (Some((l, target)), Some((r, _))) if op == BinaryOp::Sub => {
+                let difference = (l as i64).wrapping_sub(r as i64) / stride(&target);
+                return Ok(Some(self.rvalue(&self.int_type(8, true), Scalar::Int(difference))));
+            }
+            (Some(_), Some(_)) => return Err("Cannot add two pointers.".to_string()),
+            (Some((addr, target)), None) => (addr, target, self.scalar(rhs)?),
+            (None, Some((addr, target))) if op == BinaryOp::Add => (addr, target, self.scalar(lhs)?),
+            _ => return Ok(None),
+        };
+        let offset = match offset {
+            Scalar::Int(offset) => offset,
+            Scalar::Float(_) => return Err("Cannot add a floating point number to a pointer.".to_string()),
+        };
+        let offset = offset.wrapping_mul(stride(&target));
+        let addr = if op == BinaryOp::Add {
+            (addr as i64).wrapping_add(offset)
+        } else {
+            (addr as i64).wrapping_sub(offset)
+        };
+        let pointer = self.pointer_to(&target);
+        Ok(Some(self.rvalue(&pointer, Scalar::Int(addr))))
+    }
+
+    /// For pointers (and arrays, which stand for a pointer to their first element), returns the
+    /// address pointed to and the type there. Void pointers point at bytes.
+    fn pointer_target(&self, value: &Value) -> Result<Option<(usize, Type)>, String> {
+        let dtype = self.resolve(&value.dtype);
+        match &dtype.kind {
+            TypeKind::Pointer(target) => {
+                let addr = match Scalar::from_bytes(&dtype, value.bytes()?) {
+                    Scalar::Int(addr) => addr as usize,
+                    Scalar::Float(_) => unreachable!(),
+                };
+                let target = match target.and_then(|target| self.get_type(target)) {
+                    Some(target) => target,
+                    None => self.synthesize("void", 1, TypeKind::Void),
+                };
+                Ok(Some((addr, target)))
+            }
+            TypeKind::Array(element, dimensions) => {
+                let addr = value
+                    .address()
+                    .ok_or("Attempt to take address of value not located in memory.")?;
+                Ok(Some((addr, self.element_type(*element, dimensions)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn index(&self, base: &Value, index: i64) -> Result<Value, String> {
+        let dtype = self.resolve(&base.dtype);
+        if let (TypeKind::Array(element, dimensions), None) = (&dtype.kind, base.address()) {
+            // Not in memory (e.g. pieced together from registers), so take the element out of
+            // the bytes we have
+            let element = self.element_type(*element, dimensions)?;
+            let bytes = base.bytes()?;
+            let no_element = || "no such vector element".to_string();
+            if index < 0 {
+                return Err(no_element());
+            }
+            let start = (index as usize)
+                .checked_mul(element.size)
+                .ok_or_else(no_element)?;
+            let end = start
+                .checked_add(element.size)
+                .filter(|end| *end <= bytes.len())
+                .ok_or_else(no_element)?;
+            return Ok(Value {
+                bytes: Some(bytes[start..end].to_vec()),
+                dtype: element,
+                place: None,
+            });
+        }
+        let (addr, target) = self
+            .pointer_target(base)?
+            .ok_or_else(|| format!("cannot subscript something of type `{}'", dtype.name))?;
+        let offset = index.wrapping_mul(target.size as i64);
+        self.read_at(&target, (addr as i64).wrapping_add(offset) as usize)
+    }
+
+    fn member(&self, object: &Value, field: &str) -> Result<Value, String> {
+        let dtype = self.resolve(&object.dtype);
+        let members = match &dtype.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => members,
+            _ => {
+                return Err(
+                    "Attempt to extract a component of a value that is not a structure.".to_string(),
+                )
+            }
+        };
+        let member = members
+            .iter()
+            .find(|member| member.name == field)
+            .ok_or_else(|| format!("There is no member named {}.", field))?;
+        let member_type = self
+            .get_type(member.type_id)
+            .ok_or_else(|| format!("Unknown type for member {}.", field))?;
+        let offset = member
+            .offset
+            .ok_or_else(|| format!("Cannot determine the location of member {}.", field))?;
+        let end = offset + member_type.size;
+        Ok(Value {
+            bytes: match &object.bytes {
+                Some(bytes) if end <= bytes.len() => Some(bytes[offset..end].to_vec()),
+                _ => None,
+            },
+            place: object.address().map(|addr| Place::Memory(addr + offset)),
+            dtype: member_type,
+        })
+    }
+
+    fn cast(&self, dtype: &Type, value: &Value) -> Result<Value, String> {
+        let resolved = self.resolve(dtype);
+        match resolved.kind {
+            TypeKind::Base(_) | TypeKind::Enum(_) | TypeKind::Pointer(_) if resolved.is_scalar() => {
+                Ok(self.rvalue(dtype, self.scalar(value)?))
+            }
+            _ if resolved.id == self.resolve(&value.dtype).id => Ok(Value {
+                dtype: dtype.clone(),
+                ..value.clone()
+            }),
+            _ => Err("Invalid cast.".to_string()),
+        }
+    }
+
+    /// Reads a value of type `dtype` from memory.
+    fn read_at(&self, dtype: &Type, addr: usize) -> Result<Value, String> {
+        match self.resolve(dtype).kind {
+            TypeKind::Void => return Err("Attempt to take contents of a non-pointer value.".to_string()),
+            TypeKind::Function => return Err("Cannot read the code of a function as a value.".to_string()),
+            _ => {}
+        }
+        Ok(Value {
+            dtype: dtype.clone(),
+            bytes: Some(self.context.read_memory(addr, dtype.size)?),
+            place: Some(Place::Memory(addr)),
+        })
+    }
+
+    fn rvalue(&self, dtype: &Type, scalar: Scalar) -> Value {
+        Value {
+            dtype: dtype.clone(),
+            bytes: Some(scalar.to_bytes(&self.resolve(dtype))),
+            place: None,
+        }
+    }
+
+    fn is_function(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable(name) => {
+                self.context.variable(name).is_err()
+                    && self.data.get_addr_for_function(None, name).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    fn get_type(&self, id: usize) -> Option<Type> {
+        match self.data.get_type(id) {
+            Some(dtype) => Some(dtype.clone()),
+            None => self.synthetic.borrow().iter().find(|dtype| dtype.id == id).cloned(),
+        }
+    }
+
+    /// Follows typedefs of structs, unions and arrays to the type itself.
+    fn resolve(&self, dtype: &Type) -> Type {
+        self.data.resolve_alias(dtype).clone()
+    }
+
+    fn synthesize(&self, name: &str, size: usize, kind: TypeKind) -> Type {
+        let mut synthetic = self.synthetic.borrow_mut();
+        let dtype = Type {
+            id: usize::MAX - synthetic.len(),
+            ..Type::new(name.to_string(), size, kind)
+        };
+        synthetic.push(dtype.clone());
+        dtype
+    }
+
+    fn pointer_to(&self, target: &Type) -> Type {
+        let name = match &target.kind {
+            TypeKind::Void => "void *".to_string(),
+            _ if target.name.ends_with('*') => format!("{}*", target.name),
+            _ => format!("{} *", target.name),
+        };
+        let target = match target.kind {
+            TypeKind::Void => None,
+            _ => Some(target.id),
+        };
+        self.synthesize(&name, 8, TypeKind::Pointer(target))
+    }
+
+    fn int_type(&self, size: usize, signed: bool) -> Type {
+        let name = match (size, signed) {
+            (8, true) => "long",
+            (8, false) => "unsigned long",
+            (_, true) => "int",
+            (_, false) => "unsigned int",
+        };
+        let encoding = if signed {
+            Encoding::Signed
+        } else {
+            Encoding::Unsigned
+        };
+        self.synthesize(name, size, TypeKind::Base(encoding))
+    }
+
+    /// The type of the result of arithmetic on values of the two types, following C's usual
+    /// arithmetic conversions: floating point wins, small integers become int, and otherwise the
+    /// larger type wins, with unsigned winning a tie.
+    fn arithmetic_type(&self, lhs: &Type, rhs: &Type) -> Type {
+        let (lhs, rhs) = (self.resolve(lhs), self.resolve(rhs));
+        if lhs.is_float() || rhs.is_float() {
+            return self.synthesize("double", 8, TypeKind::Base(Encoding::Float));
+        }
+        let size = lhs.size.max(rhs.size).clamp(4, 8);
+        let unsigned = |dtype: &Type| !dtype.is_signed() && dtype.size >= size;
+        self.int_type(size, !unsigned(&lhs) && !unsigned(&rhs))
+    }
+
+    /// The type of an element of an array with the given element type and dimensions; indexing
+    /// a multidimensional array gives an array of the remaining dimensions.
+    fn element_type(&self, element: usize, dimensions: &[Option<usize>]) -> Result<Type, String> {
+        let element = self
+            .get_type(element)
+            .ok_or("Unknown array element type.")?;
+        if dimensions.len() <= 1 {
+            return Ok(element);
+        }
+        let rest = &dimensions[1..];
+        let mut name = format!("{} ", element.name);
+        let mut count = 1;
+        for dimension in rest {
+            match dimension {
+                Some(dimension) => {
+                    name.push_str(&format!("[{}]", dimension));
+                    count *= dimension;
+                }
+                None => name.push_str("[]"),
+            }
+        }
+        Ok(self.synthesize(&name, element.size * count, TypeKind::Array(element.id, rest.to_vec())))
+    }
+
+    /// Finds the type named in a cast, e.g. "int", "unsigned long", "struct node *" or a
+    /// typedef.
+    fn parse_type(&self, name: &str) -> Result<Type, String> {
+        let pointers = name.matches('*').count();
+        let base = name.trim_end_matches(&['*', ' '][..]);
+        let words: Vec<&str> = base
+            .split_whitespace()
+            .filter(|word| *word != "const" && *word != "volatile")
+            .collect();
+        let base = words.join(" ");
+        let mut dtype = match self.data.get_type_by_name(&base) {
+            Some(dtype) => dtype.clone(),
+            None => self
+                .base_type(&words)
+                .ok_or_else(|| format!("No symbol \"{}\" in current context.", base))?,
+        };
+        for _ in 0..pointers {
+            dtype = self.pointer_to(&dtype);
+        }
+        Ok(dtype)
+    }
+
+    /// Makes up a C base type from its keywords, for when the program never uses it.
+    fn base_type(&self, words: &[&str]) -> Option<Type> {
+        let has = |keyword: &str| words.contains(&keyword);
+        let unsigned = has("unsigned");
+        if words.iter().any(|word| !TYPE_KEYWORDS.contains(word)) {
+            return None;
+        }
+        let (size, encoding) = if has("void") {
+            return Some(self.synthesize("void", 1, TypeKind::Void));
+        } else if has("float") {
+            (4, Encoding::Float)
+        } else if has("double") {
+            (8, Encoding::Float)
+        } else if has("_Bool") {
+            (1, Encoding::Boolean)
+        } else if has("char") && unsigned {
+            (1, Encoding::UnsignedChar)
+        } else if has("char") {
+            (1, Encoding::SignedChar)
+        } else {
+            let size = if has("short") {
+                2
+            } else if has("long") {
+                8
+            } else {
+                4
+            };
+            (size, if unsigned { Encoding::Unsigned } else { Encoding::Signed })
+        };
+        Some(self.synthesize(&words.join(" "), size, TypeKind::Base(encoding)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples;
+
+    /// A program with no variables and no memory, so that only constants can be evaluated.
+    struct NoProgram;
+
+    impl Context for NoProgram {
+        fn variable(&self, name: &str) -> Result<Value, String> {
+            Err(format!("No symbol \"{}\" in current context.", name))
+        }
+
+        fn register(&self, name: &str) -> Result<u64, String> {
+            Err(format!("Invalid register `{}'", name))
+        }
+
+        fn read_memory(&self, addr: usize, _len: usize) -> Result<Vec<u8>, String> {
+            Err(format!("Cannot access memory at address {:#x}", addr))
+        }
+    }
+
+    /// Debug info to parse and evaluate against, from one of the sample programs.
+    fn debug_info() -> DwarfData {
+        DwarfData::from_file(&samples::path("hello")).unwrap()
+    }
+
+    fn eval(data: &DwarfData, text: &str) -> Result<Scalar, String> {
+        let evaluator = Evaluator::new(data, &NoProgram);
+        evaluator.scalar(&evaluator.evaluate(&parse(text, data)?)?)
+    }
+
+    fn eval_int(data: &DwarfData, text: &str) -> i64 {
+        match eval(data, text) {
+            Ok(Scalar::Int(value)) => value,
+            result => panic!("{} evaluated to {:?}", text, result),
+        }
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        let data = debug_info();
+        assert_eq!(eval_int(&data, "1+2*3"), 7);
+        assert_eq!(eval_int(&data, "10-4-3"), 3);
+        assert_eq!(eval_int(&data, "1<<2+1"), 8);
+        assert_eq!(eval_int(&data, "1 || 0 && 0"), 1);
+        match parse("a-b-c", &data).unwrap() {
+            Expr::Binary(BinaryOp::Sub, lhs, rhs) => {
+                assert!(matches!(*lhs, Expr::Binary(BinaryOp::Sub, _, _)));
+                assert!(matches!(*rhs, Expr::Variable(ref name) if name == "c"));
+            }
+            expr => panic!("a-b-c parsed as {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn casts_and_parentheses() {
+        let data = debug_info();
+        assert_eq!(eval_int(&data, "(2+3)*4"), 20);
+        assert_eq!(eval_int(&data, "(char)300"), 44);
+        assert_eq!(eval_int(&data, "(unsigned char)-1"), 255);
+        assert_eq!(eval_int(&data, "(short)(65535+2)"), 1);
+        assert_eq!(eval(&data, "(double)7/2"), Ok(Scalar::Float(3.5)));
+        assert!(matches!(parse("(int)x", &data), Ok(Expr::Cast(ref name, _)) if name == "int"));
+        assert!(matches!(parse("(x)-1", &data), Ok(Expr::Binary(BinaryOp::Sub, _, _))));
+        assert!(parse("(int x", &data).is_err());
+    }
+
+    #[test]
+    fn literals() {
+        let data = debug_info();
+        assert_eq!(eval_int(&data, "'a'"), 97);
+        assert_eq!(eval_int(&data, "'\\n'"), 10);
+        assert_eq!(eval_int(&data, "'\\0'"), 0);
+        assert_eq!(eval_int(&data, "0x1F"), 31);
+        assert_eq!(eval_int(&data, "017"), 15);
+        assert_eq!(eval_int(&data, "0"), 0);
+        assert_eq!(eval_int(&data, "10UL"), 10);
+        assert_eq!(eval(&data, "1.5e1"), Ok(Scalar::Float(15.0)));
+        assert!(eval(&data, "08").is_err());
+        assert!(eval(&data, "0xg").is_err());
+        assert!(eval(&data, "'a").is_err());
+    }
+
+    #[test]
+    fn integer_promotion_and_signedness() {
+        let data = debug_info();
+        assert_eq!(eval_int(&data, "(unsigned char)255 + 1"), 256);
+        assert_eq!(eval_int(&data, "(unsigned short)65535 * 2"), 131070);
+        assert_eq!(eval_int(&data, "-1 < 0"), 1);
+        assert_eq!(eval_int(&data, "-1 < (unsigned int)0"), 0);
+        assert_eq!(eval_int(&data, "-1 < (unsigned short)0"), 1);
+        assert_eq!(eval_int(&data, "(unsigned int)-1 / 2"), 0x7fff_ffff);
+        assert_eq!(eval_int(&data, "-7 / 2"), -3);
+        assert_eq!(eval_int(&data, "-8 >> 1"), -4);
+    }
+
+    #[test]
+    fn pointer_arithmetic() {
+        let data = debug_info();
+        assert_eq!(eval_int(&data, "(int *)0x1000 + 2"), 0x1008);
+        assert_eq!(eval_int(&data, "3 + (long *)0x1000"), 0x1018);
+        assert_eq!(eval_int(&data, "(short *)0x1000 - 1"), 0xffe);
+        assert_eq!(eval_int(&data, "(long *)0x1010 - (long *)0x1000"), 2);
+        assert_eq!(eval_int(&data, "(void *)0x1000 + 1"), 0x1001);
+        assert!(eval(&data, "(int *)0x1000 + (int *)0x1000").is_err());
+        assert!(eval(&data, "(int *)0x1000 + 1.5").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let data = debug_info();
+        assert_eq!(eval(&data, "1/0"), Err("Division by zero".to_string()));
+        assert_eq!(eval(&data, "1%0"), Err("Division by zero".to_string()));
+        assert_eq!(
+            eval(&data, "no_such_variable + 1"),
+            Err("No symbol \"no_such_variable\" in current context.".to_string())
+        );
+        assert_eq!(
+            eval(&data, "1 2"),
+            Err("A syntax error in expression, near `2'.".to_string())
+        );
+        assert!(eval(&data, "(1+2").is_err());
+        assert!(eval(&data, "1 +").is_err());
+        assert!(eval(&data, "").is_err());
+        assert!(eval(&data, "1 @ 2").is_err());
+        assert!(eval(&data, "*(int *)0x10").is_err());
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let data = debug_info();
+        let evaluator = Evaluator::new(&data, &NoProgram);
+        let int = evaluator.int_type(4, true);
+        let array = evaluator.synthesize("int [2]", 8, TypeKind::Array(int.id, vec![Some(2)]));
+        // Not in memory, as if it had been pieced together from registers
+        let value = Value {
+            dtype: array,
+            bytes: Some(vec![1, 0, 0, 0, 2, 0, 0, 0]),
+            place: None,
+        };
+        let element = evaluator.index(&value, 1).unwrap();
+        assert_eq!(evaluator.scalar(&element), Ok(Scalar::Int(2)));
+        for index in &[-1, 2, i64::MAX, i64::MIN] {
+            assert!(evaluator.index(&value, *index).is_err());
+        }
+    }
+}
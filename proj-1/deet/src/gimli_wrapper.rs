@@ -10,13 +10,18 @@ use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Encoding, Expression, File, Function, Line, Location, Member, Type, TypeKind, Variable, MAX_TYPE_DEPTH,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
@@ -49,11 +54,21 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
 
+        // Types are frequently referenced before they are declared, so collect them all before
+        // looking at any variables
+        load_types(&unit, &dwarf, &mut offset_to_type)?;
+
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // Depth of the inlined function we're inside of, if any. Its variables belong to the
+        // inlined function, not the one it was inlined into, so they're skipped.
+        let mut inlined_depth = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            if inlined_depth.map_or(false, |inlined_depth| depth <= inlined_depth) {
+                inlined_depth = None;
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -74,33 +89,6 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
@@ -129,12 +117,40 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
+                                }
+                            }
+                            gimli::DW_AT_frame_base => {
+                                if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+                                    func.frame_base = Some(get_expression(data, &unit)?);
+                                }
+                            }
+                            // An out-of-line copy of an inline function, named by the original
+                            gimli::DW_AT_abstract_origin => {
+                                let (name, return_type) = get_origin(&attr, &unit, &dwarf)?;
+                                if func.name.is_empty() {
+                                    func.name = name.unwrap_or_default();
+                                }
+                                if func.return_type.is_none() {
+                                    func.return_type =
+                                        return_type.and_then(|offset| offset_to_type.get(&offset).cloned());
+                                }
+                            }
                             _ => {}
                         }
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                gimli::DW_TAG_inlined_subroutine => {
+                    if inlined_depth.is_none() {
+                        inlined_depth = Some(depth);
+                    }
+                }
+                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable
+                    if inlined_depth.is_none() =>
+                {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
@@ -157,24 +173,44 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                                location = get_location(&attr, &unit, &dwarf)?;
+                            }
+                            gimli::DW_AT_const_value => {
+                                location = Some(Location::Value(get_const_value(&attr)?));
                             }
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
                                 }
                             }
+                            // Variables of out-of-line copies of inline functions are described
+                            // by the inline function's variables
+                            gimli::DW_AT_abstract_origin => {
+                                let (origin_name, origin_type) = get_origin(&attr, &unit, &dwarf)?;
+                                if name.is_empty() {
+                                    name = origin_name.unwrap_or_default();
+                                }
+                                if entity_type.is_none() {
+                                    entity_type =
+                                        origin_type.and_then(|offset| offset_to_type.get(&offset).cloned());
+                                }
+                            }
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // Locals without a location were optimized out, while globals without one
+                    // are just declarations of variables defined elsewhere
+                    if location.is_none() && depth > 1 {
+                        location = Some(Location::OptimizedOut);
+                    }
+                    // (Parameters of function types and prototypes have no name)
+                    if !name.is_empty() && entity_type.is_some() && location.is_some() {
                         let var = Variable {
                             name,
                             entity_type: entity_type.unwrap(),
                             location: location.unwrap(),
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
                         if depth == 1 {
                             compilation_units
@@ -201,6 +237,10 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
         }
 
         // Get line numbers
+        let comp_dir = match &unit.comp_dir {
+            Some(dir) => path::PathBuf::from(dir.to_string_lossy().as_ref()),
+            None => path::PathBuf::new(),
+        };
         if let Some(program) = unit.line_program.clone() {
             // Iterate over the line program rows.
             let mut rows = program.rows();
@@ -220,10 +260,12 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         );
                     }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                    // Get the File. Line programs may name it relative to the compilation
+                    // directory even when the unit's name isn't.
+                    let file = compilation_units.iter_mut().find(|f| {
+                        f.name == path.as_os_str().to_str().unwrap()
+                            || comp_dir.join(&f.name) == path
+                    });
 
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
@@ -240,7 +282,294 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// A type DIE as it appears in .debug_info, before the types it refers to have been read.
+struct RawType {
+    tag: gimli::DwTag,
+    name: Option<String>,
+    size: Option<usize>,
+    encoding: Option<gimli::DwAte>,
+    /// DW_AT_type: the pointed-to, element, aliased or return type
+    target: Option<usize>,
+    members: Vec<Member>,
+    dimensions: Vec<Option<usize>>,
+    enumerators: Vec<(String, i64)>,
+}
+
+/// Records every type DIE in the unit in `offset_to_type`, keyed by its .debug_info offset (the
+/// same offset that `get_attr_value` returns for `DW_AT_type` references).
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // The types enclosing the current entry, for attaching members, subranges and enumerators
+    let mut parents: Vec<(isize, usize)> = Vec::new();
+
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while parents.last().map_or(false, |(parent_depth, _)| *parent_depth >= depth) {
+            parents.pop();
+        }
+        match entry.tag() {
+            gimli::DW_TAG_base_type
+            | gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_enumeration_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_subroutine_type => {
+                let mut raw = RawType {
+                    tag: entry.tag(),
+                    name: None,
+                    size: None,
+                    encoding: None,
+                    target: None,
+                    members: Vec::new(),
+                    dimensions: Vec::new(),
+                    enumerators: Vec::new(),
+                };
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    match (attr.name(), attr.value()) {
+                        (gimli::DW_AT_encoding, gimli::AttributeValue::Encoding(encoding)) => {
+                            raw.encoding = Some(encoding)
+                        }
+                        (name, _) => match (name, get_attr_value(&attr, unit, dwarf)) {
+                            (gimli::DW_AT_name, Ok(DebugValue::Str(attr_name))) => {
+                                raw.name = Some(attr_name)
+                            }
+                            (gimli::DW_AT_byte_size, Ok(DebugValue::Uint(size))) => {
+                                raw.size = Some(size.try_into().unwrap())
+                            }
+                            (gimli::DW_AT_type, Ok(DebugValue::Size(offset))) => {
+                                raw.target = Some(offset)
+                            }
+                            _ => {}
+                        },
+                    }
+                }
+                let type_offset = debug_info_offset(entry.offset(), unit);
+                raw_types.insert(type_offset, raw);
+                parents.push((depth, type_offset));
+            }
+            gimli::DW_TAG_member | gimli::DW_TAG_subrange_type | gimli::DW_TAG_enumerator => {
+                let parent = match parents.last() {
+                    Some((parent_depth, offset)) if *parent_depth == depth - 1 => *offset,
+                    _ => continue,
+                };
+                let mut name = String::new();
+                let mut type_id = None;
+                let mut number = None;
+                // Members are at offset 0 unless they say otherwise (as union members don't)
+                let mut member_offset = Some(0);
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = get_attr_value(&attr, unit, dwarf) {
+                                name = attr_name;
+                            }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = get_attr_value(&attr, unit, dwarf) {
+                                type_id = Some(offset);
+                            }
+                        }
+                        gimli::DW_AT_data_member_location => member_offset = get_member_offset(&attr),
+                        gimli::DW_AT_count => {
+                            number = attr.udata_value().map(|value| value as i64);
+                        }
+                        // The upper bound is inclusive
+                        gimli::DW_AT_upper_bound => {
+                            number = attr.udata_value().map(|value| value as i64 + 1);
+                        }
+                        gimli::DW_AT_const_value => {
+                            number = match attr.value() {
+                                gimli::AttributeValue::Sdata(value) => Some(value),
+                                value => value.udata_value().map(|value| value as i64),
+                            };
+                        }
+                        _ => {}
+                    }
+                }
+                let raw = raw_types.get_mut(&parent).unwrap();
+                match entry.tag() {
+                    gimli::DW_TAG_member => {
+                        if let Some(type_id) = type_id {
+                            raw.members.push(Member {
+                                name,
+                                offset: member_offset,
+                                type_id,
+                            });
+                        }
+                    }
+                    gimli::DW_TAG_subrange_type => {
+                        raw.dimensions.push(number.map(|count| count as usize))
+                    }
+                    _ => raw.enumerators.push((name, number.unwrap_or(0))),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let offsets: Vec<usize> = raw_types.keys().cloned().collect();
+    for offset in offsets {
+        resolve_type(offset, &raw_types, offset_to_type, 0);
+    }
+    Ok(())
+}
+
+/// Builds the `Type` for the raw type at `offset`, along with any types its name and size
+/// depend on. Returns None for unknown types (including void).
+fn resolve_type(
+    offset: usize,
+    raw_types: &HashMap<usize, RawType>,
+    offset_to_type: &mut HashMap<usize, Type>,
+    depth: usize,
+) -> Option<Type> {
+    if let Some(dtype) = offset_to_type.get(&offset) {
+        return Some(dtype.clone());
+    }
+    let raw = raw_types.get(&offset)?;
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    let target = |offset_to_type: &mut HashMap<usize, Type>| match raw.target {
+            Some(target) => resolve_type(target, raw_types, offset_to_type, depth + 1),
+            None => None,
+        };
+    let tag_name = |keyword: &str| match &raw.name {
+        Some(name) => format!("{} {}", keyword, name),
+        None => format!("{} {{...}}", keyword),
+    };
+    let dtype = match raw.tag {
+        gimli::DW_TAG_base_type => {
+            let encoding = match raw.encoding {
+                Some(gimli::DW_ATE_float) => Encoding::Float,
+                Some(gimli::DW_ATE_boolean) => Encoding::Boolean,
+                Some(gimli::DW_ATE_signed_char) => Encoding::SignedChar,
+                Some(gimli::DW_ATE_unsigned_char) => Encoding::UnsignedChar,
+                Some(gimli::DW_ATE_unsigned) => Encoding::Unsigned,
+                _ => Encoding::Signed,
+            };
+            Type::new(
+                raw.name.clone().unwrap_or_else(|| "<unknown>".to_string()),
+                raw.size.unwrap_or(0),
+                TypeKind::Base(encoding),
+            )
+        }
+        gimli::DW_TAG_pointer_type => {
+            let name = match target(offset_to_type) {
+                Some(Type {
+                    kind: TypeKind::Function,
+                    name,
+                    ..
+                }) if name.contains(" (") => name.replacen(" (", " (*)(", 1),
+                Some(Type {
+                    kind: TypeKind::Array(..),
+                    name,
+                    ..
+                }) => name.replacen(" [", " (*)[", 1),
+                Some(Type { name, .. }) if name.ends_with('*') => format!("{}*", name),
+                Some(Type { name, .. }) => format!("{} *", name),
+                None => "void *".to_string(),
+            };
+            Type::new(name, raw.size.unwrap_or(8), TypeKind::Pointer(raw.target))
+        }
+        gimli::DW_TAG_structure_type => Type::new(
+            tag_name("struct"),
+            raw.size.unwrap_or(0),
+            TypeKind::Struct(raw.members.clone()),
+        ),
+        gimli::DW_TAG_union_type => Type::new(
+            tag_name("union"),
+            raw.size.unwrap_or(0),
+            TypeKind::Union(raw.members.clone()),
+        ),
+        gimli::DW_TAG_enumeration_type => Type::new(
+            tag_name("enum"),
+            raw.size.unwrap_or(4),
+            TypeKind::Enum(raw.enumerators.clone()),
+        ),
+        gimli::DW_TAG_array_type => {
+            let element = target(offset_to_type)?;
+            let mut name = format!("{} ", element.name);
+            for dimension in &raw.dimensions {
+                match dimension {
+                    Some(count) => write!(name, "[{}]", count).unwrap(),
+                    None => name.push_str("[]"),
+                }
+            }
+            let count: usize = raw.dimensions.iter().map(|dimension| dimension.unwrap_or(0)).product();
+            Type::new(
+                name,
+                element.size * count,
+                TypeKind::Array(raw.target.unwrap(), raw.dimensions.clone()),
+            )
+        }
+        gimli::DW_TAG_subroutine_type => {
+            let returns = target(offset_to_type).map_or("void".to_string(), |dtype| dtype.name);
+            Type::new(format!("{} ()", returns), 0, TypeKind::Function)
+        }
+        // typedef, const, volatile and restrict
+        _ => {
+            let aliased = target(offset_to_type).unwrap_or_else(|| {
+                Type::new("void".to_string(), 0, TypeKind::Void)
+            });
+            let name = match raw.tag {
+                gimli::DW_TAG_const_type => format!("const {}", aliased.name),
+                gimli::DW_TAG_volatile_type => format!("volatile {}", aliased.name),
+                gimli::DW_TAG_restrict_type => format!("{} restrict", aliased.name),
+                _ => raw.name.clone().unwrap_or(aliased.name),
+            };
+            let kind = match aliased.kind {
+                TypeKind::Struct(_) | TypeKind::Union(_) | TypeKind::Array(..) => {
+                    TypeKind::Alias(raw.target.unwrap())
+                }
+                kind => kind,
+            };
+            Type::new(name, aliased.size, kind)
+        }
+    };
+    let dtype = Type { id: offset, ..dtype };
+    offset_to_type.insert(offset, dtype.clone());
+    Some(dtype)
+}
+
+/// Evaluates a DW_AT_data_member_location. Besides a plain offset, compilers may give a location
+/// expression, of which only the usual `DW_OP_plus_uconst <offset>` is understood; for anything
+/// else the member's offset is unknown.
+fn get_member_offset<R: Reader>(attr: &gimli::Attribute<R>) -> Option<usize> {
+    let mut expr = match attr.value() {
+        gimli::AttributeValue::Exprloc(gimli::Expression(expr)) | gimli::AttributeValue::Block(expr) => expr,
+        value => return value.udata_value().map(|offset| offset as usize),
+    };
+    if expr.read_u8().ok()? != gimli::DW_OP_plus_uconst.0 {
+        return None;
+    }
+    let offset = expr.read_uleb128().ok()?;
+    if !expr.is_empty() {
+        return None;
+    }
+    Some(offset as usize)
+}
+
+fn debug_info_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -291,25 +620,78 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Location>, Error> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
+        // Globals are almost always just DW_OP_addr, which needs no frame to evaluate
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
-                }
+        if let Ok(gimli::Operation::Address { address }) = gimli::Operation::parse(&mut pc, unit.encoding()) {
+            if pc.is_empty() {
+                return Ok(Some(Location::Address(address.try_into().unwrap())));
             }
         }
+        return Ok(Some(Location::Expression(get_expression(data, unit)?)));
     }
-    None
+    let mut locations = match dwarf.attr_locations(unit, attr.value())? {
+        Some(locations) => locations,
+        None => return Ok(None),
+    };
+    let mut ranges = Vec::new();
+    while let Some(entry) = locations.next()? {
+        ranges.push((
+            entry.range.begin.try_into().unwrap(),
+            entry.range.end.try_into().unwrap(),
+            get_expression(&entry.data, unit)?,
+        ));
+    }
+    Ok(Some(Location::List(ranges)))
+}
+
+fn get_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Expression, Error> {
+    Ok(Expression {
+        bytes: data.0.to_slice()?.to_vec(),
+        encoding: unit.encoding(),
+        bias: 0,
+    })
+}
+
+/// Returns the little-endian bytes of a DW_AT_const_value.
+fn get_const_value<R: Reader>(attr: &gimli::Attribute<R>) -> Result<Vec<u8>, Error> {
+    Ok(match attr.value() {
+        gimli::AttributeValue::Block(data) => data.to_slice()?.to_vec(),
+        gimli::AttributeValue::Sdata(value) => value.to_le_bytes().to_vec(),
+        value => value.udata_value().unwrap_or(0).to_le_bytes().to_vec(),
+    })
+}
+
+/// Looks up the name and type of the entry that a DW_AT_abstract_origin refers to.
+fn get_origin<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<(Option<String>, Option<usize>), Error> {
+    let offset = match attr.value() {
+        gimli::AttributeValue::UnitRef(offset) => offset,
+        _ => return Ok((None, None)),
+    };
+    let origin = unit.entry(offset)?;
+    let mut name = None;
+    let mut entity_type = None;
+    let mut attrs = origin.attrs();
+    while let Some(attr) = attrs.next()? {
+        match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+            (gimli::DW_AT_name, Ok(DebugValue::Str(attr_name))) => name = Some(attr_name),
+            (gimli::DW_AT_type, Ok(DebugValue::Size(offset))) => entity_type = Some(offset),
+            _ => {}
+        }
+    }
+    Ok((name, entity_type))
 }
 
 // based on dwarf_dump.rs
@@ -327,16 +709,7 @@ fn get_attr_value<R: Reader>(
             dump_exprloc(w, unit.encoding(), data)?;
             Ok(DebugValue::Str(w.to_string()))
         }
-        gimli::AttributeValue::UnitRef(offset) => {
-            match offset.to_unit_section_offset(unit) {
-                UnitSectionOffset::DebugInfoOffset(goff) => {
-                    Ok(DebugValue::Size(goff.0))
-                }
-                UnitSectionOffset::DebugTypesOffset(goff) => {
-                    Ok(DebugValue::Size(goff.0))
-                }
-            }
-        }
+        gimli::AttributeValue::UnitRef(offset) => Ok(DebugValue::Size(debug_info_offset(offset, unit))),
         gimli::AttributeValue::DebugStrRef(offset) => {
             if let Ok(s) = dwarf.debug_str.get_str(offset) {
                 Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
@@ -1,26 +1,59 @@
 use nix::sys::ptrace;
 use nix::sys::signal;
+use nix::sys::uio;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::process::Child;
 use std::os::unix::process::CommandExt;
 use std::process::Command;
 use std::mem::size_of;
 use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::Duration;
 use crate::debugger::Breakpoint;
-use crate::dwarf_data::DwarfData;
+use crate::disassemble;
+use crate::dwarf_data::{DwarfData, Function};
+use crate::process::Process;
+use crate::signals::{self, SignalTable};
+
+/// Decides whether the inferior should stop at the breakpoint it just trapped on (given its
+/// address), e.g. by evaluating the breakpoint's condition. Watchpoint traps are filtered the same
+/// way, given the watched address. Returning false resumes the inferior.
+pub type StopFilter<'a> = dyn FnMut(&Inferior, usize) -> bool + 'a;
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
     Stopped(signal::Signal, usize),
 
+    /// Indicates inferior stopped because it wrote to memory covered by a hardware watchpoint.
+    /// Contains the watched address, as well as the current instruction pointer (just past the
+    /// instruction that did the write).
+    Watchpoint(usize, usize),
+
     /// Indicates inferior exited normally. Contains the exit status code.
     Exited(i32),
 
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior forked (or, if the flag is set, vforked) a child that should be
+    /// followed or kept. Contains the child, stopped, with the breakpoints it inherited.
+    Forked(Box<Inferior>, bool),
+
+    /// Indicates the inferior called exec, replacing its image (and so all our breakpoints).
+    /// Contains the instruction pointer, at the new program's entry point.
+    Execed(usize),
+}
+
+impl Status {
+    /// Returns false if the inferior is gone (it exited or was killed).
+    pub fn is_alive(&self) -> bool {
+        match self {
+            Status::Exited(_) | Status::Signaled(_) => false,
+            _ => true,
+        }
+    }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -31,123 +64,979 @@ fn child_traceme() -> Result<(), std::io::Error> {
         "ptrace TRACEME failed",
     )))
 }
+/// Offset of debug register `index` (DR0-DR7) in the `struct user` area used by PTRACE_PEEKUSER
+/// and PTRACE_POKEUSER.
+fn debugreg_offset(index: usize) -> usize {
+    let user: libc::user = unsafe { std::mem::zeroed() };
+    let base = &user as *const libc::user as usize;
+    let debugreg = &user.u_debugreg as *const _ as usize;
+    debugreg - base + index * size_of::<libc::c_ulong>()
+}
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
+
+/// What to do when the inferior forks; see "set follow-fork-mode" and "set detach-on-fork".
+#[derive(Debug, Clone, Copy)]
+pub struct ForkPolicy {
+    /// Carry on debugging the child rather than the parent
+    pub follow_child: bool,
+    /// Let the process we aren't following run free, rather than keeping it stopped
+    pub detach: bool,
+}
+
+impl Default for ForkPolicy {
+    fn default() -> Self {
+        ForkPolicy {
+            follow_child: false,
+            detach: true,
+        }
+    }
+}
+
+/// Asks for clone, fork, vfork and exec events, so that new threads and child processes are
+/// traced from birth.
+fn trace_children(pid: Pid) -> Result<(), nix::Error> {
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORKDONE
+            | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+}
+/// Sends a signal to one thread of a process.
+fn tgkill(pid: Pid, tid: Pid, signal: signal::Signal) -> Result<(), nix::Error> {
+    let res = unsafe { libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), signal as libc::c_int) };
+    nix::errno::Errno::result(res)?;
+    Ok(())
+}
+
+/// Waits for a particular thread; threads other than the main one need __WALL.
+fn wait_thread(tid: Pid, options: WaitPidFlag) -> Result<WaitStatus, nix::Error> {
+    waitpid(tid, Some(options | WaitPidFlag::__WALL))
+}
+
+/// Reads a stopped thread's floating point/SSE registers, which nix has no wrapper for.
+pub fn get_fpregs(tid: Pid) -> Result<libc::user_fpregs_struct, nix::Error> {
+    let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            &mut fpregs as *mut libc::user_fpregs_struct,
+        )
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(fpregs)
+}
+
+/// One thread of the inferior.
+pub struct Thread {
+    /// Number "info threads" shows, counting from 1 in order of creation
+    pub number: usize,
+    pub tid: Pid,
+    /// True from when we resume the thread until we have waited for it to stop
+    running: bool,
+    /// Signal to deliver the next time the thread resumes
+    pending_signal: Option<signal::Signal>,
+}
+
+impl Thread {
+    fn new(number: usize, tid: Pid) -> Thread {
+        Thread {
+            number,
+            tid,
+            running: false,
+            pending_signal: None,
+        }
+    }
+}
+
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// The process's threads, the main thread (whose tid is the pid) first. Between commands
+    /// they are all stopped: when one stops, we stop the rest.
+    threads: Vec<Thread>,
+    /// Index in `threads` of the current thread, whose registers commands see and change
+    current: usize,
+    next_thread_number: usize,
+    /// True if we attached to a process that was already running, rather than spawning it
+    attached: bool,
     pub breakpoint: HashMap<usize,Breakpoint>,
+    /// (address, length) of the watchpoint in each debug register slot that is in use
+    watchpoints: Vec<(usize, usize)>,
+    /// Which signals to stop for and which to deliver to the inferior
+    signals: SignalTable,
+    fork_policy: ForkPolicy,
+    /// Set while our breakpoints are taken out of memory we share with a vforked child that we
+    /// let go of, until the child execs or exits
+    lifted_for_vfork: bool,
 }
 
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>,breakpoint:&Vec<usize>) -> Option<Inferior> {
-        // TODO: implement me!
-        let  mut cmd = Command::new(target);
+    /// Attempts to start a new inferior process, stopped at its first instruction.
+    pub fn new(target: &str, args: &Vec<String>) -> Result<Inferior, std::io::Error> {
+        let mut cmd = Command::new(target);
         cmd.args(args);
-        unsafe{
+        unsafe {
             cmd.pre_exec(child_traceme);
         }
-        let mut child =  cmd.spawn().expect("fail to excute process");
-        //println!(
-        //    "Inferior::new not implemented! target={}, args={:?}",
-        //    target, args
-        //);
-        
-        let mut inferior = Inferior{child:child ,breakpoint:HashMap::new()};
-        let result =  inferior.wait(None).ok()?;
-        for address in breakpoint.iter(){
-            match inferior.write_byte(*address, 0xcc){
-                Ok(orig_byte) => {inferior.breakpoint.insert(*address, Breakpoint { addr: *address, orig_byte: orig_byte });}
-                Err(_) => println!("Invalid breakpoint address {:#x}",address),
+        let child = cmd.spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        let inferior = Inferior {
+            pid,
+            threads: vec![Thread::new(1, pid)],
+            current: 0,
+            next_thread_number: 2,
+            attached: false,
+            breakpoint: HashMap::new(),
+            watchpoints: Vec::new(),
+            signals: SignalTable::new(),
+            fork_policy: ForkPolicy::default(),
+            lifted_for_vfork: false,
+        };
+        inferior
+            .wait(None)
+            .and_then(|_| trace_children(inferior.pid()))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        Ok(inferior)
+    }
+
+    /// Attaches to a process that is already running, stopping all of its threads.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        ptrace::attach(pid)?;
+        let mut inferior = Inferior {
+            pid,
+            threads: vec![Thread::new(1, pid)],
+            current: 0,
+            next_thread_number: 2,
+            attached: true,
+            breakpoint: HashMap::new(),
+            watchpoints: Vec::new(),
+            signals: SignalTable::new(),
+            fork_policy: ForkPolicy::default(),
+            lifted_for_vfork: false,
+        };
+        // Wait for the SIGSTOP that PTRACE_ATTACH sends
+        inferior.wait(None)?;
+        trace_children(pid)?;
+        // PTRACE_ATTACH only takes the one thread, so attach to the others one at a time
+        let tasks = std::fs::read_dir(format!("/proc/{}/task", pid))
+            .map_err(|_| nix::Error::Sys(nix::errno::Errno::ESRCH))?;
+        for task in tasks.filter_map(Result::ok) {
+            let tid = match task.file_name().to_string_lossy().parse::<i32>() {
+                Ok(tid) if tid != pid.as_raw() => Pid::from_raw(tid),
+                _ => continue,
+            };
+            // The thread may have exited since we listed it
+            if ptrace::attach(tid).is_err() {
+                continue;
             }
-            
+            wait_thread(tid, WaitPidFlag::empty())?;
+            trace_children(tid)?;
+            inferior.threads.push(Thread::new(inferior.next_thread_number, tid));
+            inferior.next_thread_number += 1;
         }
-        Some(inferior)
+        Ok(inferior)
+    }
+
+    /// Takes charge of a child this inferior just forked. The kernel has already attached us to
+    /// it; once it stops, it has a copy of our memory, breakpoints included.
+    fn adopt_child(&self, pid: Pid) -> Result<Inferior, nix::Error> {
+        let child = Inferior {
+            pid,
+            // Only the thread that forked is copied into the child
+            threads: vec![Thread::new(1, pid)],
+            current: 0,
+            next_thread_number: 2,
+            attached: self.attached,
+            breakpoint: self.breakpoint.clone(),
+            // Debug registers aren't inherited
+            watchpoints: Vec::new(),
+            signals: self.signals.clone(),
+            fork_policy: self.fork_policy,
+            lifted_for_vfork: false,
+        };
+        // Wait for the SIGSTOP every traced child starts with
+        child.wait(None)?;
+        Ok(child)
+    }
+
+    /// Takes out every breakpoint and watchpoint, putting the original bytes back, and lets the
+    /// process carry on running without us.
+    pub fn detach(&mut self) -> Result<(), nix::Error> {
+        let addrs: Vec<usize> = self.breakpoint.keys().cloned().collect();
+        for addr in addrs {
+            self.remove_breakpoint(addr)?;
+        }
+        if !self.watchpoints.is_empty() {
+            self.set_watchpoints(&[])?;
+        }
+        for thread in self.threads.iter() {
+            ptrace::detach(thread.tid, thread.pending_signal)?;
+        }
+        Ok(())
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Returns the thread id of the current thread, which is what register and stepping
+    /// operations apply to.
+    pub fn tid(&self) -> Pid {
+        self.threads[self.current].tid
+    }
+
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    pub fn current_thread(&self) -> &Thread {
+        &self.threads[self.current]
+    }
+
+    /// Makes the thread with the given number current, returning false if there isn't one.
+    pub fn select_thread(&mut self, number: usize) -> bool {
+        match self.threads.iter().position(|thread| thread.number == number) {
+            Some(index) => {
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if this inferior was attached to rather than started by us.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    pub fn set_signal_table(&mut self, signals: SignalTable) {
+        self.signals = signals;
+    }
+
+    pub fn set_fork_policy(&mut self, fork_policy: ForkPolicy) {
+        self.fork_policy = fork_policy;
+    }
+
+    /// Sets the signal to deliver to the current thread when the inferior next resumes,
+    /// replacing the one it last stopped with. None resumes it without a signal.
+    pub fn set_pending_signal(&mut self, signal: Option<signal::Signal>) {
+        self.threads[self.current].pending_signal = signal;
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+        self.decode_wait_status(waitpid(self.pid(), options)?)
+    }
+
+    fn decode_wait_status(&self, status: WaitStatus) -> Result<Status, nix::Error> {
+        Ok(match status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+                let regs = ptrace::getregs(self.tid())?;
+                match self.watchpoint_hit(signal)? {
+                    Some(addr) => Status::Watchpoint(addr, regs.rip as usize),
+                    None => Status::Stopped(signal, regs.rip as usize),
+                }
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::PtraceEvent(_pid, _signal, event)
+                if event == ptrace::Event::PTRACE_EVENT_FORK as i32
+                    || event == ptrace::Event::PTRACE_EVENT_VFORK as i32 =>
+            {
+                let child = Pid::from_raw(ptrace::getevent(self.tid())? as i32);
+                let vfork = event == ptrace::Event::PTRACE_EVENT_VFORK as i32;
+                Status::Forked(Box::new(self.adopt_child(child)?), vfork)
+            }
+            WaitStatus::PtraceEvent(_pid, _signal, event)
+                if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 =>
+            {
+                Status::Execed(ptrace::getregs(self.tid())?.rip as usize)
+            }
+            // resume_and_wait deals with PTRACE_EVENT_VFORK_DONE and PTRACE_EVENT_CLONE itself, and
+            // we don't ask for other ptrace events or WCONTINUED, so nothing else should turn up
+            _ => return Err(nix::Error::UnsupportedOperation),
         })
     }
 
-    pub fn inferior_continue(&mut self) -> Result<Status,nix::Error>{
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        if self.breakpoint.contains_key(&(rip-1)){
-            //okknni println!("stopped at a breakpoint");
-            let orig = self.breakpoint.get(&(rip-1)).unwrap().orig_byte;
-            self.write_byte(rip-1, orig)?;
+    pub fn inferior_continue(&mut self, should_stop: &mut StopFilter) -> Result<Status,nix::Error>{
+        loop {
+            let rip = ptrace::getregs(self.tid())?.rip as usize;
+            if self.breakpoint.contains_key(&rip) {
+                // Get off the breakpoint before letting the inferior run
+                match self.step_instruction()? {
+                    Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+                    Status::Watchpoint(addr, _) if !should_stop(self, addr) => {}
+                    other => return Ok(other),
+                }
+            }
+            match self.resume_and_wait(false)? {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) if self.breakpoint.contains_key(&(rip - 1)) => {
+                    // The 0xcc has already executed; back up so rip points at the breakpoint itself
+                    let mut regs = ptrace::getregs(self.tid())?;
+                    regs.rip = (rip - 1) as u64;
+                    ptrace::setregs(self.tid(), regs)?;
+                    if should_stop(self, rip - 1) {
+                        return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip - 1));
+                    }
+                }
+                Status::Watchpoint(addr, rip) => {
+                    if should_stop(self, addr) {
+                        return Ok(Status::Watchpoint(addr, rip));
+                    }
+                }
+                other => return Ok(other),
+            }
+        }
+    }
+
+    /// Continues every thread (or with `single_step`, steps the current one), delivering pending
+    /// signals, and waits for a thread to stop, which becomes the current thread. The others are
+    /// then stopped too. Signals the policy doesn't stop for are handed straight back to the
+    /// inferior (or not, per the policy) without returning, as are new threads.
+    fn resume_and_wait(&mut self, single_step: bool) -> Result<Status, nix::Error> {
+        loop {
+            if single_step {
+                let thread = &mut self.threads[self.current];
+                ptrace::step(thread.tid, thread.pending_signal.take())?;
+                thread.running = true;
+            } else {
+                for thread in self.threads.iter_mut().filter(|thread| !thread.running) {
+                    ptrace::cont(thread.tid, thread.pending_signal.take())?;
+                    thread.running = true;
+                }
+            }
+            let (index, status) = self.wait_for_thread()?;
+            self.current = index;
+            let status = match status {
+                WaitStatus::PtraceEvent(_pid, _signal, event)
+                    if event == ptrace::Event::PTRACE_EVENT_VFORK_DONE as i32 =>
+                {
+                    // The vforked child has exec'd or exited, so our memory is our own again
+                    if self.lifted_for_vfork {
+                        self.lifted_for_vfork = false;
+                        self.write_breakpoint_bytes(true)?;
+                    }
+                    continue;
+                }
+                WaitStatus::PtraceEvent(tid, _signal, event)
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+                {
+                    self.add_thread(Pid::from_raw(ptrace::getevent(tid)? as i32))?;
+                    continue;
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _) if tid != self.pid => {
+                    self.remove_thread(index);
+                    if !single_step {
+                        continue;
+                    }
+                    // The thread we were stepping is gone, so carry on with the main thread, unless
+                    // the whole process is on its way out
+                    self.current = 0;
+                    match ptrace::getregs(self.tid()) {
+                        Ok(regs) => return Ok(Status::Stopped(signal::Signal::SIGTRAP, regs.rip as usize)),
+                        Err(_) => return self.wait_for_exit(),
+                    }
+                }
+                status => self.decode_wait_status(status)?,
+            };
+            let (signal, rip) = match status {
+                Status::Forked(child, vfork) if !self.fork_policy.follow_child && self.fork_policy.detach => {
+                    self.detach_child(*child, vfork)?;
+                    continue;
+                }
+                // SIGTRAP is how breakpoints and steps stop, so it's never the program's
+                Status::Stopped(signal, rip) if signal != signal::Signal::SIGTRAP => (signal, rip),
+                Status::Execed(rip) => {
+                    // Exec does away with every thread but the one that called it, which takes over
+                    // the pid, and the old image goes with them, our breakpoints and watchpoints
+                    // included
+                    for thread in self.threads.drain(1..) {
+                        let _ = wait_thread(thread.tid, WaitPidFlag::WNOHANG);
+                    }
+                    self.current = 0;
+                    self.breakpoint.clear();
+                    self.watchpoints.clear();
+                    return Ok(Status::Execed(rip));
+                }
+                Status::Exited(_) | Status::Signaled(_) => return Ok(status),
+                other => {
+                    self.stop_all()?;
+                    return Ok(other);
+                }
+            };
+            let policy = self.signals.policy(signal);
+            if policy.pass {
+                self.threads[self.current].pending_signal = Some(signal);
+            }
+            if policy.stop {
+                self.stop_all()?;
+                return Ok(Status::Stopped(signal, rip));
+            }
+            if policy.print {
+                println!("Program received signal {}, {}.", signal, signals::description(signal));
+            }
+        }
+    }
 
-            regs.rip = (rip-1)  as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
+    /// Waits until one of the running threads stops (or exits), returning its index in
+    /// `threads` and what happened to it.
+    fn wait_for_thread(&mut self) -> Result<(usize, WaitStatus), nix::Error> {
+        let running: Vec<usize> = (0..self.threads.len())
+            .filter(|&index| self.threads[index].running)
+            .collect();
+        if running.len() == 1 {
+            let status = wait_thread(self.threads[running[0]].tid, WaitPidFlag::empty())?;
+            self.threads[running[0]].running = false;
+            return Ok((running[0], status));
+        }
+        // There's no waiting for any one of a set of threads, so poll them
+        loop {
+            for &index in running.iter() {
+                match wait_thread(self.threads[index].tid, WaitPidFlag::WNOHANG)? {
+                    WaitStatus::StillAlive => {}
+                    status => {
+                        self.threads[index].running = false;
+                        return Ok((index, status));
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
 
-            ptrace::step(self.pid(), None).unwrap();
-            
-            match self.wait(None).unwrap(){
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)), 
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                Status::Stopped(_, _) => {
-                    // restore 0xcc in the breakpoint location
-                    self.write_byte(rip - 1, 0xcc).unwrap();
+    /// Stops every thread that is still running, so that nothing runs while the user looks
+    /// around. Anything else a thread reports on the way is kept for later where possible: a
+    /// breakpoint hit is undone so that it happens again when the thread resumes, and a signal is
+    /// delivered then.
+    fn stop_all(&mut self) -> Result<(), nix::Error> {
+        let mut index = 0;
+        while index < self.threads.len() {
+            if !self.threads[index].running {
+                index += 1;
+                continue;
+            }
+            let tid = self.threads[index].tid;
+            tgkill(self.pid, tid, signal::Signal::SIGSTOP)?;
+            let exited = loop {
+                match wait_thread(tid, WaitPidFlag::empty())? {
+                    WaitStatus::Stopped(_tid, signal::Signal::SIGSTOP) => break false,
+                    WaitStatus::Exited(..) | WaitStatus::Signaled(..) => break true,
+                    WaitStatus::Stopped(_tid, signal::Signal::SIGTRAP) => {
+                        let mut regs = ptrace::getregs(tid)?;
+                        if self.breakpoint.contains_key(&(regs.rip as usize - 1)) {
+                            regs.rip -= 1;
+                            ptrace::setregs(tid, regs)?;
+                        } else if !self.watchpoints.is_empty() {
+                            // A watchpoint write can't be redone, and the thread that stopped first
+                            // will show the new value anyway; just don't let DR6 report it later
+                            self.poke_debugreg(tid, 6, 0)?;
+                        }
+                    }
+                    WaitStatus::Stopped(_tid, signal) => {
+                        self.threads[index].pending_signal = Some(signal);
+                    }
+                    WaitStatus::PtraceEvent(_tid, _signal, event)
+                        if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+                    {
+                        self.add_thread(Pid::from_raw(ptrace::getevent(tid)? as i32))?;
+                    }
+                    _ => {}
                 }
+                // Our SIGSTOP is still to come
+                ptrace::cont(tid, None)?;
+            };
+            if exited {
+                self.remove_thread(index);
+            } else {
+                self.threads[index].running = false;
+                index += 1;
             }
         }
-        ptrace::cont(self.pid(), None)?;
+        Ok(())
+    }
+
+    /// Starts tracking a thread the inferior just created. It starts out stopped, with the
+    /// watchpoints set for the other threads.
+    fn add_thread(&mut self, tid: Pid) -> Result<(), nix::Error> {
+        // Wait for the SIGSTOP every traced thread starts with
+        wait_thread(tid, WaitPidFlag::empty())?;
+        let thread = Thread::new(self.next_thread_number, tid);
+        self.next_thread_number += 1;
+        println!("[New Thread {} (LWP {})]", thread.number, tid);
+        self.threads.push(thread);
+        let watchpoints = self.watchpoints.clone();
+        self.program_debugregs(tid, &watchpoints)
+    }
+
+    fn remove_thread(&mut self, index: usize) {
+        let thread = self.threads.remove(index);
+        println!("[Thread {} (LWP {}) exited]", thread.number, thread.tid);
+        if self.current > index {
+            self.current -= 1;
+        } else if self.current == index {
+            self.current = 0;
+        }
+    }
+
+    /// Reaps what is left of the threads of a process that is exiting, then the process itself.
+    fn wait_for_exit(&mut self) -> Result<Status, nix::Error> {
+        for thread in self.threads.drain(1..) {
+            let _ = wait_thread(thread.tid, WaitPidFlag::empty());
+        }
+        self.current = 0;
         self.wait(None)
     }
+
+    /// Lets a child we aren't following go. A forked child has its own copy of our breakpoints to
+    /// take out; a vforked one shares our memory until it execs or exits, so we take ours out
+    /// until then rather than have it trip over them.
+    fn detach_child(&mut self, mut child: Inferior, vfork: bool) -> Result<(), nix::Error> {
+        if vfork {
+            self.write_breakpoint_bytes(false)?;
+            self.lifted_for_vfork = true;
+            ptrace::detach(child.pid(), None)?;
+        } else {
+            child.detach()?;
+        }
+        println!("[Detaching after {} from child process {}]", if vfork { "vfork" } else { "fork" }, child.pid());
+        Ok(())
+    }
+
+    /// Writes 0xcc (`installed`) or the original byte back at every breakpoint, without
+    /// forgetting about any of them.
+    fn write_breakpoint_bytes(&mut self, installed: bool) -> Result<(), nix::Error> {
+        let breakpoints: Vec<Breakpoint> = self.breakpoint.values().cloned().collect();
+        for bp in breakpoints {
+            self.write_byte(bp.addr, if installed { 0xcc } else { bp.orig_byte })?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single machine instruction. If the inferior is sitting on a breakpoint, the
+    /// original instruction is executed and the 0xcc put back afterwards.
+    pub fn step_instruction(&mut self) -> Result<Status, nix::Error> {
+        let rip = ptrace::getregs(self.tid())?.rip as usize;
+        let orig_byte = match self.breakpoint.get(&rip) {
+            Some(bp) => Some(bp.orig_byte),
+            None => None,
+        };
+        if let Some(orig_byte) = orig_byte {
+            self.write_byte(rip, orig_byte)?;
+        }
+        let status = self.resume_and_wait(true)?;
+        let same_image = match status {
+            Status::Execed(_) => false,
+            _ => status.is_alive(),
+        };
+        if orig_byte.is_some() && same_image {
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(status)
+    }
+
+    /// Executes `count` machine instructions. With `step_over`, a call counts as one instruction:
+    /// the callee is run until it returns. Stops early at breakpoints approved by `should_stop`.
+    pub fn step_instructions(
+        &mut self,
+        count: usize,
+        step_over: bool,
+        should_stop: &mut StopFilter,
+    ) -> Result<Status, nix::Error> {
+        let mut status = Status::Stopped(signal::Signal::SIGTRAP, ptrace::getregs(self.tid())?.rip as usize);
+        for _ in 0..count {
+            let regs = ptrace::getregs(self.tid())?;
+            let rip = regs.rip as usize;
+            let call_length = if step_over {
+                self.read_code(rip, disassemble::MAX_INSTRUCTION_LEN)
+                    .ok()
+                    .and_then(|bytes| disassemble::call_length(&bytes, rip))
+            } else {
+                None
+            };
+            status = match call_length {
+                // The call has returned once rsp is back where it is now
+                Some(len) => self.run_to_frame(rip + len, regs.rsp as usize, should_stop)?,
+                None => self.step_instruction()?,
+            };
+            let rip = match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+                Status::Watchpoint(addr, rip) if !should_stop(self, addr) => rip,
+                other => return Ok(other),
+            };
+            status = Status::Stopped(signal::Signal::SIGTRAP, rip);
+            if call_length.map_or(false, |len| rip != regs.rip as usize + len) {
+                // Stopped at a breakpoint inside the call
+                return Ok(status);
+            }
+            if self.breakpoint.contains_key(&rip) && should_stop(self, rip) {
+                return Ok(status);
+            }
+        }
+        Ok(status)
+    }
+
+    /// Source-level single step: runs until the inferior reaches the start of a different line.
+    /// With `step_into`, calls to functions that have debug info are entered; otherwise (and for
+    /// calls into code without line info) the call is run to completion.
+    pub fn step_line(
+        &mut self,
+        data: &DwarfData,
+        step_into: bool,
+        should_stop: &mut StopFilter,
+    ) -> Result<Status, nix::Error> {
+        let start_rip = ptrace::getregs(self.tid())?.rip as usize;
+        let start_line = data.get_line_from_addr(start_rip).map(|line| line.number);
+        let mut func_range = data.get_function_for_addr(start_rip).map(Function::text_range);
+        let mut returned = false;
+        loop {
+            let prev = ptrace::getregs(self.tid())?;
+            let prev_top = ptrace::read(self.tid(), prev.rsp as ptrace::AddressType)? as usize;
+            let mut rip = match self.step_instruction()? {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+                Status::Watchpoint(addr, rip) if !should_stop(self, addr) => rip,
+                other => return Ok(other),
+            };
+            let regs = ptrace::getregs(self.tid())?;
+            if self.breakpoint.contains_key(&rip) && should_stop(self, rip) {
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+            }
+
+            if regs.rsp == prev.rsp - 8 {
+                let ret_addr = ptrace::read(self.tid(), regs.rsp as ptrace::AddressType)? as usize;
+                if ret_addr > prev.rip as usize && ret_addr <= prev.rip as usize + 16 && rip != ret_addr {
+                    // We just executed a call instruction
+                    if step_into {
+                        if let Some(callee) = data.get_function_for_addr(rip) {
+                            if callee.address == rip {
+                                return self.run_to(data.get_prologue_end(callee), should_stop);
+                            }
+                        }
+                    }
+                    match self.run_to_frame(ret_addr, regs.rsp as usize + 8, should_stop)? {
+                        Status::Stopped(signal::Signal::SIGTRAP, stop) if stop == ret_addr => {
+                            rip = ret_addr
+                        }
+                        other => return Ok(other),
+                    }
+                }
+            } else if regs.rsp == prev.rsp + 8 && rip == prev_top {
+                // We just returned from the function we were stepping through
+                match data.get_function_for_addr(rip) {
+                    Some(caller) => func_range = Some(caller.text_range()),
+                    // No line info to step through, so let the caller run
+                    None => return self.inferior_continue(should_stop),
+                }
+                returned = true;
+                continue;
+            }
+
+            let in_func = match func_range {
+                Some((low, high)) => low <= rip && rip < high,
+                None => false,
+            };
+            if !in_func {
+                if let Some(func) = data.get_function_for_addr(rip) {
+                    // Jumped into a function without calling it (e.g. a tail call)
+                    func_range = Some(func.text_range());
+                    returned = true;
+                }
+            }
+            if data.is_line_start(rip) {
+                let line = data.get_line_from_addr(rip).map(|line| line.number);
+                if returned || line != start_line {
+                    return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+                }
+            }
+        }
+    }
+
+    /// Returns the return address and canonical frame address (the value of rsp once the frame
+    /// returns) of the innermost frame, taking into account that the frame may still be in its
+    /// prologue.
+    pub fn frame_return_address(&self, data: &DwarfData) -> Result<(usize, usize), nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let rip = regs.rip as usize;
+        let rsp = regs.rsp as usize;
+        if let Some(func) = data.get_function_for_addr(rip) {
+            // Find where the prologue pushes rbp and then copies rsp into it
+            let code = self.read_code(func.address, 16)?;
+            let mut offset = 0;
+            if code.starts_with(&[0xf3, 0x0f, 0x1e, 0xfa]) {
+                // endbr64
+                offset += 4;
+            }
+            if code[offset] == 0x55 {
+                let push_addr = func.address + offset;
+                if rip <= push_addr {
+                    let ret_addr = ptrace::read(self.tid(), rsp as ptrace::AddressType)? as usize;
+                    return Ok((ret_addr, rsp + 8));
+                }
+                if rip <= push_addr + 1 && code[offset + 1..].starts_with(&[0x48, 0x89, 0xe5]) {
+                    let ret_addr =
+                        ptrace::read(self.tid(), (rsp + 8) as ptrace::AddressType)? as usize;
+                    return Ok((ret_addr, rsp + 16));
+                }
+            }
+        }
+        let rbp = regs.rbp as usize;
+        let ret_addr = ptrace::read(self.tid(), (rbp + 8) as ptrace::AddressType)? as usize;
+        Ok((ret_addr, rbp + 16))
+    }
+
+    /// Continues until the current thread reaches `addr`, using a temporary breakpoint if there
+    /// is no breakpoint there already. Stops early if anything else stops the inferior.
+    pub fn run_to(&mut self, addr: usize, should_stop: &mut StopFilter) -> Result<Status, nix::Error> {
+        let tid = self.tid();
+        let mut should_stop = |inferior: &Inferior, rip: usize| {
+            (rip == addr && inferior.tid() == tid) || should_stop(inferior, rip)
+        };
+        if self.breakpoint.contains_key(&addr) {
+            return self.inferior_continue(&mut should_stop);
+        }
+        self.insert_breakpoint(addr)?;
+        let status = self.inferior_continue(&mut should_stop);
+        if let Ok(true) = status.as_ref().map(Status::is_alive) {
+            self.remove_breakpoint(addr)?;
+        }
+        status
+    }
+
+    /// Like `run_to`, but ignores hits of `addr` from deeper (recursive) frames: the inferior is
+    /// only considered to have arrived once rsp has popped back up to `cfa`.
+    pub fn run_to_frame(
+        &mut self,
+        addr: usize,
+        cfa: usize,
+        should_stop: &mut StopFilter,
+    ) -> Result<Status, nix::Error> {
+        loop {
+            let status = self.run_to(addr, should_stop)?;
+            if let Status::Stopped(signal::Signal::SIGTRAP, rip) = status {
+                if rip == addr && (ptrace::getregs(self.tid())?.rsp as usize) < cfa {
+                    continue;
+                }
+            }
+            return Ok(status);
+        }
+    }
+
+    /// Programs the x86-64 debug registers so that writes to each of `watchpoints` (address,
+    /// length) trap. The hardware has four slots; lengths must be 1, 2, 4 or 8 and the address
+    /// aligned to the length.
+    pub fn set_watchpoints(&mut self, watchpoints: &[(usize, usize)]) -> Result<(), nix::Error> {
+        if watchpoints.len() > 4 {
+            return Err(nix::Error::Sys(nix::errno::Errno::ENOSPC));
+        }
+        // Every thread has its own debug registers
+        let tids: Vec<Pid> = self.threads.iter().map(|thread| thread.tid).collect();
+        for tid in tids {
+            self.program_debugregs(tid, watchpoints)?;
+        }
+        self.watchpoints = watchpoints.to_vec();
+        Ok(())
+    }
+
+    fn program_debugregs(&self, tid: Pid, watchpoints: &[(usize, usize)]) -> Result<(), nix::Error> {
+        // Disable everything first, since the kernel validates DR7 against the address registers
+        self.poke_debugreg(tid, 7, 0)?;
+        let mut dr7 = 0;
+        for (slot, (addr, len)) in watchpoints.iter().enumerate() {
+            self.poke_debugreg(tid, slot, *addr as u64)?;
+            let len_bits = match len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                _ => 0b11,
+            };
+            // Local enable, break on data writes, and the length
+            dr7 |= 1 << (2 * slot);
+            dr7 |= 0b01 << (16 + 4 * slot);
+            dr7 |= len_bits << (18 + 4 * slot);
+        }
+        self.poke_debugreg(tid, 7, dr7)
+    }
+
+    /// If a SIGTRAP was caused by a watchpoint rather than a breakpoint or single step, returns
+    /// the watched address. DR6 records which slot fired; it is cleared for the next trap.
+    fn watchpoint_hit(&self, signal: signal::Signal) -> Result<Option<usize>, nix::Error> {
+        if signal != signal::Signal::SIGTRAP || self.watchpoints.is_empty() {
+            return Ok(None);
+        }
+        let dr6 = self.peek_debugreg(self.tid(), 6)?;
+        let slot = (0..self.watchpoints.len()).find(|slot| dr6 & (1 << slot) != 0);
+        if slot.is_some() {
+            self.poke_debugreg(self.tid(), 6, 0)?;
+        }
+        Ok(slot.map(|slot| self.watchpoints[slot].0))
+    }
+
+    fn peek_debugreg(&self, tid: Pid, index: usize) -> Result<u64, nix::Error> {
+        let res = unsafe {
+            nix::errno::Errno::clear();
+            libc::ptrace(
+                libc::PTRACE_PEEKUSER,
+                tid.as_raw(),
+                debugreg_offset(index) as *mut libc::c_void,
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        // PEEKUSER returns the value itself, so -1 is only an error if errno was set
+        if res == -1 && nix::errno::errno() != 0 {
+            return Err(nix::Error::Sys(nix::errno::Errno::last()));
+        }
+        Ok(res as u64)
+    }
+
+    fn poke_debugreg(&self, tid: Pid, index: usize, value: u64) -> Result<(), nix::Error> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_POKEUSER,
+                tid.as_raw(),
+                debugreg_offset(index) as *mut libc::c_void,
+                value as *mut libc::c_void,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(())
+    }
+
     pub fn kill(&mut self){
         let pid = self.pid();
-        self.child.kill().expect("kill command wasn't running");
-        waitpid(pid,None);
+        let _ = signal::kill(pid, signal::Signal::SIGKILL);
+        // The main thread isn't reported until the others have been reaped
+        for thread in self.threads.iter().skip(1) {
+            let _ = wait_thread(thread.tid, WaitPidFlag::empty());
+        }
+        let _ = waitpid(pid,None);
         println!("Killing running inferior (pid {})",self.pid());
     }
 
-    pub fn print_backtrace(&self, data:&DwarfData) -> Result<(), nix::Error>{
-        let regs =  ptrace::getregs(self.pid()).ok().unwrap();
-        let mut instruction_ptr = regs.rip as usize;
-        let mut base_ptr = regs.rbp as usize;
-        while true{
-            let file = data.get_line_from_addr(instruction_ptr);
-            let func = data.get_function_from_addr(instruction_ptr);
-            println!("{} ({}:{})",func.as_ref().unwrap(),file.as_ref().unwrap().file,file.as_ref().unwrap().number);
-            if func.as_ref().unwrap()=="main"{
-                break;
-            }
-            instruction_ptr = ptrace::read(self.pid(),(base_ptr+8) as ptrace::AddressType)? as usize ;
-            base_ptr  = ptrace::read(self.pid(), base_ptr as ptrace::AddressType )? as usize;
+    /// Installs a 0xcc at `addr`, remembering the byte it replaced. Does nothing if there is
+    /// already a breakpoint there.
+    pub fn insert_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if !self.breakpoint.contains_key(&addr) {
+            let orig_byte = self.write_byte(addr, 0xcc)?;
+            self.breakpoint.insert(addr, Breakpoint { addr, orig_byte });
         }
-        
         Ok(())
     }
 
-    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        ptrace::write(
+    /// Removes the breakpoint at `addr`, if any, putting the original byte back.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if let Some(bp) = self.breakpoint.remove(&addr) {
+            self.write_byte(addr, bp.orig_byte)?;
+        }
+        Ok(())
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`, in one system call. Fails
+    /// unless every byte can be read.
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = vec![0u8; len];
+        if len == 0 {
+            return Ok(bytes);
+        }
+        let read = uio::process_vm_readv(
             self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
+            &[uio::IoVec::from_mut_slice(&mut bytes)],
+            &[uio::RemoteIoVec { base: addr, len }],
         )?;
-        Ok(orig_byte as u8)
+        if read < len {
+            return Err(nix::Error::Sys(nix::errno::Errno::EFAULT));
+        }
+        Ok(bytes)
+    }
+
+    /// Reads memory like `read_bytes`, but shows the original bytes where breakpoints are
+    /// installed, which is what's wanted when reading code.
+    pub fn read_code(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.read_bytes(addr, len)?;
+        for bp in self.breakpoint.values() {
+            if addr <= bp.addr && bp.addr - addr < len {
+                bytes[bp.addr - addr] = bp.orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Writes a single byte, returning the byte that was there before.
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
+        Ok(self.write_bytes(addr, &[val])?[0])
+    }
+
+    /// Writes `bytes` at `addr` a word at a time with ptrace, which (unlike process_vm_writev)
+    /// can write to read-only pages such as the program's code. Returns the bytes that were there
+    /// before.
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<Vec<u8>, nix::Error> {
+        let end = addr + bytes.len();
+        let mut orig_bytes = Vec::with_capacity(bytes.len());
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < end {
+            let word = ptrace::read(self.tid(), word_addr as ptrace::AddressType)? as u64;
+            let mut word_bytes = word.to_le_bytes();
+            for (offset, byte) in word_bytes.iter_mut().enumerate() {
+                let byte_addr = word_addr + offset;
+                if addr <= byte_addr && byte_addr < end {
+                    orig_bytes.push(*byte);
+                    *byte = bytes[byte_addr - addr];
+                }
+            }
+            ptrace::write(
+                self.tid(),
+                word_addr as ptrace::AddressType,
+                u64::from_le_bytes(word_bytes) as *mut std::ffi::c_void,
+            )?;
+            word_addr += size_of::<usize>();
+        }
+        Ok(orig_bytes)
+    }
+
+    /// Writes memory on the user's behalf. Bytes that land under a breakpoint replace the byte
+    /// the breakpoint saved, so the 0xcc stays in place.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut bytes = bytes.to_vec();
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            if let Some(bp) = self.breakpoint.get_mut(&(addr + offset)) {
+                bp.orig_byte = *byte;
+                *byte = 0xcc;
+            }
+        }
+        self.write_bytes(addr, &bytes).map(|_| ())
+    }
+}
+
+impl Process for Inferior {
+    fn regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.tid())
+    }
+
+    fn fpregs(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        get_fpregs(self.tid())
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        Inferior::read_bytes(self, addr, len)
+    }
+
+    fn read_code(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        Inferior::read_code(self, addr, len)
+    }
+
+    fn auxv_entry(&self, key: u64) -> Option<u64> {
+        let auxv = std::fs::read(format!("/proc/{}/auxv", self.pid)).ok()?;
+        let word = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+        auxv.chunks_exact(16)
+            .map(|pair| (word(&pair[..8]), word(&pair[8..])))
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
     }
 }
@@ -0,0 +1,199 @@
+//! Evaluation of DWARF location expressions and location lists, which say where a variable lives
+//! at a given point in the program: in memory, in a register, spread over several of those, or
+//! nowhere at all once the compiler has optimized it out.
+
+use crate::dwarf_data::{Expression, Function, Location};
+use crate::unwind::NUM_REGISTERS;
+
+/// Where a variable's value is at the moment.
+#[derive(Debug, Clone)]
+pub enum Place {
+    Memory(usize),
+    /// A register, by DWARF number
+    Register(u16),
+    /// A value computed by the expression, which doesn't live anywhere the debugger can change
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+/// The registers of the stack frame a location is evaluated in.
+pub struct FrameState {
+    /// Address to look up location lists at. For callers, this is just before the return
+    /// address, which may be past the end of the call's scope.
+    pub pc: usize,
+    /// Canonical frame address, for DW_OP_call_frame_cfa
+    pub cfa: usize,
+    /// General purpose registers and the return address by DWARF number, where known
+    pub registers: [Option<usize>; NUM_REGISTERS],
+    /// xmm0-15 (DWARF registers 17-32), if known. Empty for callers, since they aren't saved.
+    pub vector_registers: Vec<[u8; 16]>,
+}
+
+/// First DWARF register number of xmm0
+const XMM0: u16 = 17;
+
+impl FrameState {
+    /// Reads the low `size` bytes of a register, given its DWARF number.
+    pub fn read_register(&self, register: u16, size: usize) -> Result<Vec<u8>, String> {
+        let mut bytes = if register < XMM0 {
+            match self.registers.get(register as usize).cloned().unwrap_or(None) {
+                Some(value) => value.to_le_bytes().to_vec(),
+                None => return Err(format!("Value of DWARF register {} is not available.", register)),
+            }
+        } else {
+            match self.vector_registers.get((register - XMM0) as usize) {
+                Some(value) => value.to_vec(),
+                None => return Err(format!("Value of DWARF register {} is not available.", register)),
+            }
+        };
+        bytes.resize(size, 0);
+        Ok(bytes)
+    }
+}
+
+/// Works out where a variable of `size` bytes in `function` is. Without a frame, only fixed
+/// locations (globals and constants) can be found. `read_memory` reads the inferior's memory,
+/// for expressions that dereference pointers.
+pub fn locate(
+    location: &Location,
+    size: usize,
+    function: Option<&Function>,
+    frame: Option<&FrameState>,
+    read_memory: &dyn Fn(usize, usize) -> Option<Vec<u8>>,
+) -> Result<Place, String> {
+    let frame_base = function.and_then(|function| function.frame_base.as_ref());
+    match location {
+        Location::Address(addr) => Ok(Place::Memory(*addr)),
+        Location::Value(bytes) => Ok(Place::Value(bytes.clone())),
+        Location::OptimizedOut => Ok(Place::OptimizedOut),
+        Location::Expression(expr) => {
+            let frame = frame.ok_or("No frame selected.")?;
+            evaluate(expr, size, frame_base, frame, read_memory)
+        }
+        Location::List(ranges) => {
+            let frame = frame.ok_or("No frame selected.")?;
+            match ranges
+                .iter()
+                .find(|(start, end, _)| *start <= frame.pc && frame.pc < *end)
+            {
+                Some((_, _, expr)) => evaluate(expr, size, frame_base, frame, read_memory),
+                // Not live here
+                None => Ok(Place::OptimizedOut),
+            }
+        }
+    }
+}
+
+fn evaluate(
+    expr: &Expression,
+    size: usize,
+    frame_base: Option<&Expression>,
+    frame: &FrameState,
+    read_memory: &dyn Fn(usize, usize) -> Option<Vec<u8>>,
+) -> Result<Place, String> {
+    let bytes = gimli::EndianSlice::new(&expr.bytes, gimli::RunTimeEndian::Little);
+    let mut evaluation = gimli::Expression(bytes).evaluation(expr.encoding);
+    let invalid = |err: gimli::Error| format!("Invalid DWARF expression: {}", err);
+    let read_word = |addr: u64, len: u8| {
+        read_memory(addr as usize, len as usize)
+            .map(|bytes| to_u64(&bytes))
+            .ok_or_else(|| format!("Cannot access memory at address {:#x}", addr))
+    };
+
+    let mut result = evaluation.evaluate().map_err(invalid)?;
+    loop {
+        result = match result {
+            gimli::EvaluationResult::Complete => break,
+            gimli::EvaluationResult::RequiresMemory { address, size, .. } => {
+                let value = read_word(address, size)?;
+                evaluation.resume_with_memory(gimli::Value::Generic(value))
+            }
+            gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                let value = to_u64(&frame.read_register(register.0, 8)?);
+                evaluation.resume_with_register(gimli::Value::Generic(value))
+            }
+            gimli::EvaluationResult::RequiresFrameBase => {
+                let frame_base = frame_base.ok_or("Function has no frame base.")?;
+                let base = match evaluate(frame_base, 8, None, frame, read_memory)? {
+                    Place::Memory(addr) => addr as u64,
+                    Place::Register(register) => to_u64(&frame.read_register(register, 8)?),
+                    Place::Value(bytes) => to_u64(&bytes),
+                    Place::OptimizedOut => return Err("Frame base is not available.".to_string()),
+                };
+                evaluation.resume_with_frame_base(base)
+            }
+            gimli::EvaluationResult::RequiresCallFrameCfa => {
+                evaluation.resume_with_call_frame_cfa(frame.cfa as u64)
+            }
+            gimli::EvaluationResult::RequiresRelocatedAddress(addr) => {
+                evaluation.resume_with_relocated_address(addr.wrapping_add(expr.bias as u64))
+            }
+            // The value a parameter had on entry to the function, which is long gone unless we
+            // go looking at the caller's call site information
+            gimli::EvaluationResult::RequiresEntryValue(_) => return Ok(Place::OptimizedOut),
+            other => return Err(format!("Unsupported DWARF expression ({:?})", other)),
+        }
+        .map_err(invalid)?;
+    }
+
+    let pieces = evaluation.result();
+    match pieces.as_slice() {
+        [] => return Ok(Place::OptimizedOut),
+        [piece] if piece.size_in_bits.is_none() => return piece_place(piece),
+        _ => {}
+    }
+    // Pieced together from several places, so read the value now
+    let mut value: Vec<u8> = Vec::new();
+    for piece in &pieces {
+        let len = match piece.size_in_bits {
+            Some(bits) => (bits as usize + 7) / 8,
+            None => size.saturating_sub(value.len()),
+        };
+        match piece_place(piece)? {
+            Place::Memory(addr) => value.extend(
+                read_memory(addr, len)
+                    .ok_or_else(|| format!("Cannot access memory at address {:#x}", addr))?,
+            ),
+            Place::Register(register) => value.extend(frame.read_register(register, len)?),
+            Place::Value(mut bytes) => {
+                bytes.resize(len, 0);
+                value.extend(bytes);
+            }
+            // Part of the variable is gone; gdb shows those parts as <optimized out>, but we
+            // don't track values that finely
+            Place::OptimizedOut => return Ok(Place::OptimizedOut),
+        }
+    }
+    Ok(Place::Value(value))
+}
+
+fn piece_place<R: gimli::Reader>(piece: &gimli::Piece<R>) -> Result<Place, String> {
+    match &piece.location {
+        gimli::Location::Empty => Ok(Place::OptimizedOut),
+        gimli::Location::Register { register } => Ok(Place::Register(register.0)),
+        gimli::Location::Address { address } => Ok(Place::Memory(*address as usize)),
+        gimli::Location::Value { value } => Ok(Place::Value(value_bytes(*value))),
+        gimli::Location::Bytes { value } => value
+            .to_slice()
+            .map(|bytes| Place::Value(bytes.to_vec()))
+            .map_err(|err| format!("Invalid DWARF expression: {}", err)),
+        gimli::Location::ImplicitPointer { .. } => {
+            Err("Cannot show a pointer the compiler optimized away.".to_string())
+        }
+    }
+}
+
+fn value_bytes(value: gimli::Value) -> Vec<u8> {
+    match value {
+        gimli::Value::F32(value) => value.to_bits().to_le_bytes().to_vec(),
+        gimli::Value::F64(value) => value.to_bits().to_le_bytes().to_vec(),
+        value => value.to_u64(!0).unwrap_or(0).to_le_bytes().to_vec(),
+    }
+}
+
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
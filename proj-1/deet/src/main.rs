@@ -1,23 +1,60 @@
 mod debugger;
 mod debugger_command;
+mod debugger_error;
 mod inferior;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
+mod condition;
+mod core_file;
+mod disassemble;
 mod dwarf_data;
+mod examine;
+mod expression;
 mod gimli_wrapper;
+mod location;
+mod process;
+mod registers;
+#[cfg(test)]
+mod samples;
+mod shared_libraries;
+mod signals;
+mod unwind;
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    let (target, pid, core) = match args.len() {
+        2 => (args[1].clone(), None, None),
+        3 if args[1] == "--pid" => (format!("/proc/{}/exe", args[2]), Some(&args[2]), None),
+        4 if args[2] == "--core" => (args[1].clone(), None, Some(&args[3])),
+        _ => {
+            println!("Usage: {} <target program>", args[0]);
+            println!("       {} <target program> --core <core file>", args[0]);
+            println!("       {} --pid <pid>", args[0]);
+            std::process::exit(1);
+        }
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = match Debugger::new(&target) {
+        Ok(debugger) => debugger,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(pid) = pid {
+        if let Err(err) = debugger.attach(pid) {
+            println!("{}", err);
+        }
+    }
+    if let Some(core) = core {
+        if let Err(err) = debugger.load_core(core) {
+            println!("{}", err);
+        }
+    }
+    debugger.run();
 }
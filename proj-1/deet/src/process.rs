@@ -0,0 +1,42 @@
+//! What the commands that only look at a program need from it: registers and memory. These come
+//! from a live, stopped inferior or, for post-mortem debugging, from a core file.
+
+use crate::dwarf_data::DwarfData;
+use crate::unwind::{self, Frame};
+use std::convert::TryInto;
+
+// Keys of auxiliary vector entries
+/// Base address of the dynamic linker
+pub const AT_BASE: u64 = 7;
+/// Address of the program's entry point
+pub const AT_ENTRY: u64 = 9;
+
+pub trait Process {
+    /// Reads the general purpose registers of the current thread.
+    fn regs(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Reads the floating point/SSE registers, which hold float and double return values.
+    fn fpregs(&self) -> Result<libc::user_fpregs_struct, nix::Error>;
+
+    /// Reads `len` bytes of memory starting at `addr`. Fails unless every byte can be read.
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    /// Reads memory like `read_bytes`, but without any breakpoints the debugger has installed,
+    /// which is what's wanted when reading code.
+    fn read_code(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        self.read_bytes(addr, len)
+    }
+
+    /// Returns the value of an entry in the auxiliary vector the kernel gave the program.
+    fn auxv_entry(&self, key: u64) -> Option<u64>;
+
+    /// Unwinds the current thread's stack, innermost frame first.
+    fn backtrace(&self, data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.regs()?;
+        let read_word = |addr: usize| {
+            let bytes = self.read_bytes(addr, 8).ok()?;
+            Some(usize::from_le_bytes(bytes[..].try_into().ok()?))
+        };
+        Ok(unwind::backtrace(data, &regs, &read_word))
+    }
+}
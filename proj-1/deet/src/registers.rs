@@ -0,0 +1,118 @@
+use libc::user_regs_struct;
+
+/// Register names in the order "info registers" shows them.
+pub const NAMES: [&str; 26] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs", "fs_base", "gs_base",
+];
+
+/// General purpose registers by DWARF register number (on x86-64, 16 is the return address,
+/// i.e. rip).
+const DWARF_NAMES: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+
+/// Bit positions of the eflags bits worth showing.
+const EFLAGS: [(u32, &str); 15] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+    (14, "NT"),
+    (16, "RF"),
+    (17, "VM"),
+    (18, "AC"),
+    (19, "VIF"),
+    (21, "ID"),
+];
+
+/// Looks up a general purpose register by name (without the leading `$`). Accepts the 64-bit
+/// names, plus `pc`, `sp` and `fp` as aliases for rip, rsp and rbp.
+pub fn get(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    field(&mut regs, name).map(|value| *value)
+}
+
+/// Changes a register in `regs`, returning false if there is no register by that name.
+pub fn set(regs: &mut user_regs_struct, name: &str, value: u64) -> bool {
+    match field(regs, name) {
+        Some(field) => {
+            *field = value;
+            true
+        }
+        None => false,
+    }
+}
+
+fn field<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" | "fp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    })
+}
+
+/// Names a general purpose register given its DWARF register number, if it is one.
+pub fn dwarf_register(register: u16) -> Option<&'static str> {
+    DWARF_NAMES.get(register as usize).cloned()
+}
+
+/// Names any register given its DWARF register number, for messages.
+pub fn dwarf_name(register: u16) -> String {
+    match register {
+        0..=16 => DWARF_NAMES[register as usize].to_string(),
+        17..=32 => format!("xmm{}", register - 17),
+        _ => format!("<DWARF register {}>", register),
+    }
+}
+
+/// Returns true for registers that hold addresses, which are best shown in hex.
+pub fn is_pointer(name: &str) -> bool {
+    match name {
+        "rip" | "pc" | "rsp" | "sp" | "rbp" | "fp" | "fs_base" | "gs_base" => true,
+        _ => false,
+    }
+}
+
+/// Lists the flags set in an eflags value, e.g. "[ ZF PF IF ]".
+pub fn describe_eflags(value: u64) -> String {
+    let mut flags = String::from("[ ");
+    for (bit, name) in EFLAGS.iter() {
+        if value & (1 << bit) != 0 {
+            flags.push_str(name);
+            flags.push(' ');
+        }
+    }
+    flags.push(']');
+    flags
+}
@@ -0,0 +1,22 @@
+//! The sample programs, for tests that need a real executable to work with.
+
+use once_cell::sync::Lazy;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Held while running make, so that tests running in parallel don't build a sample at once
+static MAKE: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Builds samples/<name>.c, if it hasn't been already, and returns the path of the program.
+pub fn path(name: &str) -> String {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let _make = MAKE.lock().unwrap_or_else(|err| err.into_inner());
+    let status = Command::new("make")
+        .arg(format!("samples/{}", name))
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .status()
+        .expect("couldn't run make");
+    assert!(status.success(), "couldn't build samples/{}", name);
+    format!("{}/samples/{}", dir, name)
+}
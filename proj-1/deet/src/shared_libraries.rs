@@ -0,0 +1,110 @@
+//! Where a dynamically linked program and its shared libraries are in memory.
+//!
+//! A PIE is loaded at a random address, which the kernel tells the program in its auxiliary
+//! vector: the difference between `AT_ENTRY` and the entry point in the ELF header is how far it
+//! was moved. The dynamic linker keeps a list of the libraries it has loaded (the `link_map`s
+//! hanging off `struct r_debug`), which it publishes through the `DT_DEBUG` entry of the program's
+//! dynamic section, and calls `_dl_debug_state` each time the list changes so that debuggers can
+//! put a breakpoint there.
+
+use crate::dwarf_data::DwarfData;
+use crate::process::{Process, AT_BASE, AT_ENTRY};
+use std::convert::TryInto;
+
+const DT_NULL: usize = 0;
+const DT_DEBUG: usize = 21;
+/// Size of an entry in the dynamic section: a tag and a value
+const DYN_SIZE: usize = 16;
+/// Give up on a dynamic section or library list that goes on this long, as it's probably garbage
+const MAX_ENTRIES: usize = 4096;
+
+// Offsets into struct r_debug and struct link_map
+const R_DEBUG_MAP: usize = 8;
+const LINK_MAP_ADDR: usize = 0;
+const LINK_MAP_NAME: usize = 8;
+const LINK_MAP_NEXT: usize = 24;
+
+/// Longest library path we'll read, and how much of it to read at a time
+const MAX_PATH: usize = 4096;
+const STRING_CHUNK: usize = 64;
+const PAGE_SIZE: usize = 4096;
+
+fn read_word(process: &dyn Process, addr: usize) -> Option<usize> {
+    let bytes = process.read_bytes(addr, 8).ok()?;
+    Some(usize::from_le_bytes(bytes[..].try_into().ok()?))
+}
+
+fn read_c_string(process: &dyn Process, addr: usize) -> Option<String> {
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_PATH {
+        // Read a chunk at a time, without crossing into a page that may not be mapped
+        let start = addr + bytes.len();
+        let len = STRING_CHUNK.min(PAGE_SIZE - start % PAGE_SIZE);
+        let chunk = process.read_bytes(start, len).ok()?;
+        match chunk.iter().position(|byte| *byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Returns how far the program was moved from its link-time addresses when it was loaded (0 for
+/// executables that aren't position independent).
+pub fn load_bias(process: &dyn Process, data: &DwarfData) -> Option<usize> {
+    let entry = process.auxv_entry(AT_ENTRY)? as usize;
+    Some(entry.wrapping_sub(data.entry()))
+}
+
+/// Finds the dynamic linker's `struct r_debug`, which isn't filled in until the dynamic linker
+/// has started up.
+fn r_debug(process: &dyn Process, data: &DwarfData) -> Option<usize> {
+    let dynamic = data.dynamic_address()?;
+    for index in 0..MAX_ENTRIES {
+        let entry = dynamic + index * DYN_SIZE;
+        match read_word(process, entry)? {
+            DT_NULL => return None,
+            DT_DEBUG => return read_word(process, entry + 8).filter(|addr| *addr != 0),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Lists the shared libraries the program has loaded, as (path, load bias) pairs, starting with
+/// the dynamic linker. `data` must already have the program's own load bias.
+pub fn libraries(process: &dyn Process, data: &DwarfData) -> Vec<(String, usize)> {
+    let mut libraries = Vec::new();
+    let interpreter_base = process.auxv_entry(AT_BASE).unwrap_or(0) as usize;
+    // The dynamic linker is there from the start, but only shows up in its own list later (and
+    // maybe under another name), so go by the auxiliary vector for it
+    if let Some(interpreter) = data.interpreter() {
+        if interpreter_base != 0 {
+            libraries.push((interpreter.to_string(), interpreter_base));
+        }
+    }
+    let mut link_map = r_debug(process, data)
+        .and_then(|r_debug| read_word(process, r_debug + R_DEBUG_MAP))
+        .unwrap_or(0);
+    for _ in 0..MAX_ENTRIES {
+        if link_map == 0 {
+            break;
+        }
+        let load_bias = match read_word(process, link_map + LINK_MAP_ADDR) {
+            Some(load_bias) => load_bias,
+            None => break,
+        };
+        let path = read_word(process, link_map + LINK_MAP_NAME)
+            .and_then(|name| read_c_string(process, name))
+            .unwrap_or_default();
+        // The program itself comes first, with no name
+        if !path.is_empty() && load_bias != interpreter_base {
+            libraries.push((path, load_bias));
+        }
+        link_map = read_word(process, link_map + LINK_MAP_NEXT).unwrap_or(0);
+    }
+    libraries
+}
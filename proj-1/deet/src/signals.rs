@@ -0,0 +1,184 @@
+//! What to do when the inferior receives a signal: whether to stop, whether to say so, and
+//! whether to deliver the signal to the program when it resumes. Set with "handle".
+
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// How one signal is treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    /// Stop the inferior and return to the prompt
+    pub stop: bool,
+    /// Report the signal, even if not stopping for it
+    pub print: bool,
+    /// Deliver the signal to the inferior when it resumes
+    pub pass: bool,
+}
+
+/// Signals programs use in the normal course of things, which aren't worth stopping for.
+const QUIET: [Signal; 7] = [
+    Signal::SIGALRM,
+    Signal::SIGURG,
+    Signal::SIGCHLD,
+    Signal::SIGWINCH,
+    Signal::SIGIO,
+    Signal::SIGVTALRM,
+    Signal::SIGPROF,
+];
+
+#[derive(Debug, Clone)]
+pub struct SignalTable {
+    policies: HashMap<Signal, Policy>,
+}
+
+impl SignalTable {
+    /// Starts out with gdb's defaults: stop for and pass on everything, except the signals in
+    /// `QUIET`, which are passed on silently, and SIGINT and SIGTRAP, which are meant for the
+    /// debugger rather than the program.
+    pub fn new() -> SignalTable {
+        let mut policies = HashMap::new();
+        for signal in Signal::iterator() {
+            let policy = if QUIET.contains(&signal) {
+                Policy {
+                    stop: false,
+                    print: false,
+                    pass: true,
+                }
+            } else {
+                Policy {
+                    stop: true,
+                    print: true,
+                    pass: signal != Signal::SIGINT && signal != Signal::SIGTRAP,
+                }
+            };
+            policies.insert(signal, policy);
+        }
+        SignalTable { policies }
+    }
+
+    pub fn policy(&self, signal: Signal) -> Policy {
+        self.policies.get(&signal).cloned().unwrap_or(Policy {
+            stop: true,
+            print: true,
+            pass: true,
+        })
+    }
+
+    /// Applies one of "handle"'s keywords to a signal. As in gdb, stopping implies printing and
+    /// not printing implies not stopping; "ignore" is another word for "nopass".
+    pub fn apply(&mut self, signal: Signal, keyword: &str) -> Result<(), String> {
+        let mut policy = self.policy(signal);
+        match keyword {
+            "stop" => {
+                policy.stop = true;
+                policy.print = true;
+            }
+            "nostop" => policy.stop = false,
+            "print" => policy.print = true,
+            "noprint" => {
+                policy.print = false;
+                policy.stop = false;
+            }
+            "pass" | "noignore" => policy.pass = true,
+            "nopass" | "ignore" => policy.pass = false,
+            _ => return Err(format!("Unrecognized or ambiguous flag word: \"{}\".", keyword)),
+        }
+        self.policies.insert(signal, policy);
+        Ok(())
+    }
+}
+
+/// Parses a signal as "SIGUSR1", "usr1" or its number.
+pub fn parse(name: &str) -> Result<Signal, String> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number)
+            .map_err(|_| "Only signals 1-31 are valid as numeric signals.".to_string());
+    }
+    let upper = name.to_uppercase();
+    let full = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+    Signal::from_str(&full).map_err(|_| format!("Unknown signal \"{}\".", name))
+}
+
+/// The usual description of a signal, as strsignal gives it.
+pub fn description(signal: Signal) -> &'static str {
+    match signal {
+        Signal::SIGHUP => "Hangup",
+        Signal::SIGINT => "Interrupt",
+        Signal::SIGQUIT => "Quit",
+        Signal::SIGILL => "Illegal instruction",
+        Signal::SIGTRAP => "Trace/breakpoint trap",
+        Signal::SIGABRT => "Aborted",
+        Signal::SIGBUS => "Bus error",
+        Signal::SIGFPE => "Arithmetic exception",
+        Signal::SIGKILL => "Killed",
+        Signal::SIGUSR1 => "User defined signal 1",
+        Signal::SIGSEGV => "Segmentation fault",
+        Signal::SIGUSR2 => "User defined signal 2",
+        Signal::SIGPIPE => "Broken pipe",
+        Signal::SIGALRM => "Alarm clock",
+        Signal::SIGTERM => "Terminated",
+        Signal::SIGSTKFLT => "Stack fault",
+        Signal::SIGCHLD => "Child status changed",
+        Signal::SIGCONT => "Continued",
+        Signal::SIGSTOP => "Stopped (signal)",
+        Signal::SIGTSTP => "Stopped (user)",
+        Signal::SIGTTIN => "Stopped (tty input)",
+        Signal::SIGTTOU => "Stopped (tty output)",
+        Signal::SIGURG => "Urgent I/O condition",
+        Signal::SIGXCPU => "CPU time limit exceeded",
+        Signal::SIGXFSZ => "File size limit exceeded",
+        Signal::SIGVTALRM => "Virtual timer expired",
+        Signal::SIGPROF => "Profiling timer expired",
+        Signal::SIGWINCH => "Window size changed",
+        Signal::SIGIO => "I/O possible",
+        Signal::SIGPWR => "Power fail/restart",
+        Signal::SIGSYS => "Bad system call",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signals() {
+        for name in &["SIGINT", "INT", "int", "SigInt", "2"] {
+            assert_eq!(parse(name), Ok(Signal::SIGINT));
+        }
+        assert_eq!(parse("usr1"), Ok(Signal::SIGUSR1));
+        assert_eq!(parse("SIGFOO"), Err("Unknown signal \"SIGFOO\".".to_string()));
+        assert!(parse("").is_err());
+        assert!(parse("0").is_err());
+        assert!(parse("99").is_err());
+        assert!(parse("-2").is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let table = SignalTable::new();
+        let policy = |stop, print, pass| Policy { stop, print, pass };
+        assert_eq!(table.policy(Signal::SIGSEGV), policy(true, true, true));
+        assert_eq!(table.policy(Signal::SIGINT), policy(true, true, false));
+        assert_eq!(table.policy(Signal::SIGCHLD), policy(false, false, true));
+    }
+
+    #[test]
+    fn apply_keywords() {
+        let mut table = SignalTable::new();
+        table.apply(Signal::SIGUSR1, "noprint").unwrap();
+        assert!(!table.policy(Signal::SIGUSR1).stop);
+        table.apply(Signal::SIGUSR1, "stop").unwrap();
+        assert!(table.policy(Signal::SIGUSR1).print);
+        table.apply(Signal::SIGUSR1, "ignore").unwrap();
+        assert!(!table.policy(Signal::SIGUSR1).pass);
+        table.apply(Signal::SIGUSR1, "noignore").unwrap();
+        assert!(table.policy(Signal::SIGUSR1).pass);
+        assert!(table.apply(Signal::SIGUSR1, "sometimes").is_err());
+    }
+}
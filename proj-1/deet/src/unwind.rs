@@ -0,0 +1,210 @@
+//! Stack unwinding using the call frame information (CFI) in `.eh_frame` and `.debug_frame`, so
+//! that backtraces work even when code doesn't keep a frame pointer in rbp.
+
+use crate::dwarf_data::{DwarfData, Line};
+use gimli::UnwindSection;
+use object::{Object, ObjectSection};
+use std::borrow;
+use std::rc::Rc;
+
+type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+// DWARF register numbers on x86-64
+const RBX: u16 = 3;
+const RBP: u16 = 6;
+const RSP: u16 = 7;
+const RA: u16 = 16;
+pub const NUM_REGISTERS: usize = 17;
+
+/// Refuse to unwind forever on a corrupted stack
+const MAX_FRAMES: usize = 1024;
+
+pub struct CallFrameInfo {
+    eh_frame: Option<gimli::EhFrame<Reader>>,
+    debug_frame: Option<gimli::DebugFrame<Reader>>,
+    bases: gimli::BaseAddresses,
+    /// Where the object was loaded; the tables themselves hold link-time addresses
+    load_bias: usize,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+        let load_section = |name: &str| -> Option<Reader> {
+            let data = object.section_data_by_name(name)?;
+            if data.is_empty() {
+                return None;
+            }
+            let data: Rc<[u8]> = Rc::from(borrow::Cow::into_owned(data).into_boxed_slice());
+            Some(gimli::EndianRcSlice::new(data, endian))
+        };
+        let address_of = |name: &str| object.section_by_name(name).map_or(0, |s| s.address());
+
+        let mut debug_frame = load_section(".debug_frame").map(gimli::DebugFrame::from);
+        if let Some(debug_frame) = debug_frame.as_mut() {
+            debug_frame.set_address_size(8);
+        }
+        CallFrameInfo {
+            eh_frame: load_section(".eh_frame").map(gimli::EhFrame::from),
+            debug_frame,
+            bases: gimli::BaseAddresses::default()
+                .set_eh_frame(address_of(".eh_frame"))
+                .set_text(address_of(".text")),
+            load_bias: 0,
+        }
+    }
+
+    pub fn set_load_bias(&mut self, load_bias: usize) {
+        self.load_bias = load_bias;
+    }
+
+    /// Finds the unwind rules that apply at `pc`, preferring `.eh_frame`.
+    fn row_for_address(&self, pc: usize) -> Option<gimli::UnwindTableRow<Reader>> {
+        let pc = pc.wrapping_sub(self.load_bias);
+        if let Some(eh_frame) = &self.eh_frame {
+            let mut ctx = gimli::UninitializedUnwindContext::new();
+            let row = eh_frame.unwind_info_for_address(
+                &self.bases,
+                &mut ctx,
+                pc as u64,
+                gimli::EhFrame::cie_from_offset,
+            );
+            if let Ok(row) = row {
+                return Some(row);
+            }
+        }
+        let debug_frame = self.debug_frame.as_ref()?;
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        debug_frame
+            .unwind_info_for_address(
+                &self.bases,
+                &mut ctx,
+                pc as u64,
+                gimli::DebugFrame::cie_from_offset,
+            )
+            .ok()
+    }
+}
+
+/// One frame of a backtrace.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: usize,
+    /// Canonical frame address: the value of rsp in the caller just before the call
+    pub cfa: usize,
+    /// Values of the general purpose registers (by DWARF number) while this frame is executing,
+    /// where known. Only callee-saved registers survive into callers.
+    pub registers: [Option<usize>; NUM_REGISTERS],
+    pub function: Option<String>,
+    pub line: Option<Line>,
+}
+
+/// Walks the stack starting from the given registers. `read_word` reads a word of the inferior's
+/// memory, returning None if it can't. Stops at main, at the outermost frame, or when the stack
+/// stops making sense.
+pub fn backtrace(
+    data: &DwarfData,
+    regs: &libc::user_regs_struct,
+    read_word: &dyn Fn(usize) -> Option<usize>,
+) -> Vec<Frame> {
+    let mut registers: [Option<usize>; NUM_REGISTERS] = [None; NUM_REGISTERS];
+    let initial = [
+        regs.rax, regs.rdx, regs.rcx, regs.rbx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ];
+    for (register, value) in registers.iter_mut().zip(initial.iter()) {
+        *register = Some(*value as usize);
+    }
+
+    let mut frames: Vec<Frame> = Vec::new();
+    while let Some(pc) = registers[RA as usize] {
+        if pc == 0 || frames.len() >= MAX_FRAMES {
+            break;
+        }
+        // Return addresses point after the call, which may be past the end of the function
+        let lookup_pc = if frames.is_empty() { pc } else { pc - 1 };
+        let caller = match data.call_frame_info(lookup_pc).row_for_address(lookup_pc) {
+            Some(row) => unwind_with_cfi(&row, &registers, read_word),
+            None => unwind_with_frame_pointer(&registers, read_word),
+        };
+        let (cfa, caller) = match caller {
+            Some(result) => result,
+            None => (0, [None; NUM_REGISTERS]),
+        };
+
+        let function = data.get_function_from_addr(lookup_pc);
+        let is_main = function.as_ref().map_or(false, |name| name == "main");
+        frames.push(Frame {
+            pc,
+            cfa,
+            registers,
+            function,
+            line: data.get_line_from_addr(lookup_pc),
+        });
+        // The stack grows down, so each caller's frame must be above its callee's
+        let prev_sp = registers[RSP as usize].unwrap_or(0);
+        if is_main || cfa == 0 || cfa <= prev_sp {
+            break;
+        }
+        registers = caller;
+    }
+    frames
+}
+
+/// Computes the CFA and the caller's registers from a CFI row.
+fn unwind_with_cfi(
+    row: &gimli::UnwindTableRow<Reader>,
+    registers: &[Option<usize>; NUM_REGISTERS],
+    read_word: &dyn Fn(usize) -> Option<usize>,
+) -> Option<(usize, [Option<usize>; NUM_REGISTERS])> {
+    let cfa = match row.cfa() {
+        gimli::CfaRule::RegisterAndOffset { register, offset } => {
+            let base = (*registers.get(register.0 as usize)?)?;
+            (base as i64 + offset) as usize
+        }
+        // DWARF expressions for the CFA only show up in hand-written assembly (e.g. the PLT)
+        gimli::CfaRule::Expression(_) => return None,
+    };
+
+    let mut caller = [None; NUM_REGISTERS];
+    for regno in 0..NUM_REGISTERS as u16 {
+        caller[regno as usize] = match row.register(gimli::Register(regno)) {
+            // Registers without a rule keep their value, except the return address, for which
+            // "undefined" marks the outermost frame
+            gimli::RegisterRule::Undefined if regno == RA => None,
+            gimli::RegisterRule::Undefined | gimli::RegisterRule::SameValue => {
+                registers[regno as usize]
+            }
+            gimli::RegisterRule::Offset(offset) => read_word((cfa as i64 + offset) as usize),
+            gimli::RegisterRule::ValOffset(offset) => Some((cfa as i64 + offset) as usize),
+            gimli::RegisterRule::Register(other) => {
+                registers.get(other.0 as usize).cloned().unwrap_or(None)
+            }
+            _ => None,
+        };
+    }
+    // Only callee-saved registers survive a call; the rest are unknown in the caller
+    for regno in 0..NUM_REGISTERS as u16 {
+        let callee_saved = regno == RBX || regno == RBP || (12..=15).contains(&regno);
+        if !callee_saved && regno != RA {
+            caller[regno as usize] = None;
+        }
+    }
+    caller[RSP as usize] = Some(cfa);
+    Some((cfa, caller))
+}
+
+/// Falls back to following the saved rbp chain, for code without CFI.
+fn unwind_with_frame_pointer(
+    registers: &[Option<usize>; NUM_REGISTERS],
+    read_word: &dyn Fn(usize) -> Option<usize>,
+) -> Option<(usize, [Option<usize>; NUM_REGISTERS])> {
+    let rbp = registers[RBP as usize]?;
+    if rbp == 0 {
+        return None;
+    }
+    let mut caller = [None; NUM_REGISTERS];
+    caller[RA as usize] = Some(read_word(rbp + 8)?);
+    caller[RBP as usize] = Some(read_word(rbp)?);
+    caller[RSP as usize] = Some(rbp + 16);
+    Some((rbp + 16, caller))
+}